@@ -1,6 +1,12 @@
 use std::ops::Range;
 
+use symphonia_core::errors::{Result, decode_error, unsupported_error};
+
 use crate::M4AType;
+use crate::meta::{AAC_SAMPLE_RATES, AudioSpecificConfig};
+
+const ADTS_HEADER_LEN: usize = 7;
+const ADTS_CRC_LEN: usize = 2;
 
 // largely copied from https://github.com/probablykasper/redlux/blob/ad2022affa3d50b9f95c16b9450837d21ca32c55/src/adts.rs
 // See LICENSE in this folder
@@ -68,3 +74,97 @@ fn get_bits_u8(byte: u8, range: Range<u8>) -> u8 {
     let moved_back = shaved_left >> (range.start - 1);
     moved_back >> (8 - range.end)
 }
+
+/// Fixed header fields that describe the stream in an ADTS frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct AdtsConfig {
+    profile: u8,
+    sample_rate_index: u8,
+    channel_config: u8,
+}
+
+impl AdtsConfig {
+    /// Returns the header fields that ADTS frames of a stream with this config would have, or
+    /// `None` if ADTS can't signal it.
+    pub(crate) fn from_asc(asc: &AudioSpecificConfig) -> Option<Self> {
+        let object_type = asc.object_type as u8;
+        let sample_rate_index = AAC_SAMPLE_RATES[..13]
+            .iter()
+            .position(|rate| *rate == asc.sample_rate)?;
+        ((1..=4).contains(&object_type) && asc.channel_config < 8).then_some(Self {
+            profile: object_type - 1,
+            sample_rate_index: sample_rate_index as u8,
+            channel_config: asc.channel_config,
+        })
+    }
+
+    fn read(header: &[u8]) -> Self {
+        Self {
+            profile: header[2] >> 6,
+            sample_rate_index: (header[2] >> 2) & 0x0f,
+            channel_config: (header[2] & 0x01) << 2 | header[3] >> 6,
+        }
+    }
+}
+
+/// A complete ADTS frame found in a packet.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AdtsFrame<'a> {
+    /// The whole frame, including the header.
+    pub(crate) data: &'a [u8],
+    pub(crate) config: AdtsConfig,
+    header_len: usize,
+    raw_data_blocks: u8,
+}
+
+impl<'a> AdtsFrame<'a> {
+    /// The raw data block after the header.
+    pub(crate) fn payload(&self) -> Result<&'a [u8]> {
+        // Frames with multiple blocks can only be decoded as ADTS
+        if self.raw_data_blocks > 1 {
+            return unsupported_error("aac: adts frames with multiple raw data blocks");
+        }
+        Ok(&self.data[self.header_len..])
+    }
+}
+
+/// Checks if the data starts with an ADTS sync word and an AAC layer.
+pub(crate) fn has_adts_header(data: &[u8]) -> bool {
+    data.len() >= ADTS_HEADER_LEN && data[0] == 0xff && data[1] & 0xf6 == 0xf0
+}
+
+/// Splits a packet into the ADTS frames it contains. Every byte of the packet must belong to a
+/// frame.
+pub(crate) fn split_adts_frames(mut data: &[u8]) -> Result<Vec<AdtsFrame<'_>>> {
+    let mut frames = Vec::new();
+    while !data.is_empty() {
+        if !has_adts_header(data) {
+            return decode_error("aac: missing adts sync word");
+        }
+        let protection_absent = data[1] & 0x01 != 0;
+        let frame_len = (usize::from(data[3] & 0x03) << 11)
+            | (usize::from(data[4]) << 3)
+            | usize::from(data[5] >> 5);
+        let raw_data_blocks = (data[6] & 0x03) + 1;
+        let header_len = if protection_absent {
+            ADTS_HEADER_LEN
+        } else {
+            ADTS_HEADER_LEN + ADTS_CRC_LEN
+        };
+        if frame_len <= header_len {
+            return decode_error("aac: invalid adts frame length");
+        }
+        if frame_len > data.len() {
+            return decode_error("aac: adts frame length exceeds packet length");
+        }
+        let (frame, rest) = data.split_at(frame_len);
+        frames.push(AdtsFrame {
+            data: frame,
+            config: AdtsConfig::read(frame),
+            header_len,
+            raw_data_blocks,
+        });
+        data = rest;
+    }
+    Ok(frames)
+}
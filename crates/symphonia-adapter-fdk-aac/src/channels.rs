@@ -0,0 +1,131 @@
+use fdk_aac_sys as sys;
+use symphonia_core::audio::{Channels, Position};
+
+/// Output channel layout of the decoder.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ChannelLayout {
+    pub(crate) channels: Channels,
+    /// Index of the decoder output channel for each channel in Symphonia's canonical order.
+    pub(crate) order: Vec<usize>,
+}
+
+impl ChannelLayout {
+    /// Maps the output channels described by the stream info to speaker positions. Layouts that
+    /// can't be represented by Symphonia's positions are output as discrete channels in the
+    /// decoder's order.
+    pub(crate) fn from_stream_info(info: &sys::CStreamInfo) -> Self {
+        let num_channels = info.numChannels.max(0) as usize;
+        let positions =
+            if num_channels == 0 || info.pChannelType.is_null() || info.pChannelIndices.is_null() {
+                None
+            } else {
+                let (types, indices) = unsafe {
+                    (
+                        std::slice::from_raw_parts(info.pChannelType, num_channels),
+                        std::slice::from_raw_parts(info.pChannelIndices, num_channels),
+                    )
+                };
+                positions(types, indices)
+            };
+
+        match positions {
+            Some(positions) => Self::positioned(&positions),
+            None => Self::discrete(num_channels),
+        }
+    }
+
+    fn positioned(positions: &[Position]) -> Self {
+        let channels = Channels::Positioned(
+            positions
+                .iter()
+                .fold(Position::empty(), |acc, pos| acc.union(*pos)),
+        );
+        let mut order = vec![0; positions.len()];
+        for (idx, pos) in positions.iter().enumerate() {
+            if let Some(canonical_idx) = channels.get_canonical_index_for_positioned_channel(*pos) {
+                order[canonical_idx] = idx;
+            }
+        }
+        Self { channels, order }
+    }
+
+    fn discrete(num_channels: usize) -> Self {
+        Self {
+            channels: Channels::Discrete(num_channels as u16),
+            order: (0..num_channels).collect(),
+        }
+    }
+
+    /// Returns `true` if the decoder output is already in canonical order.
+    pub(crate) fn is_canonical(&self) -> bool {
+        self.order.iter().enumerate().all(|(i, idx)| i == *idx)
+    }
+}
+
+/// Speaker position of each output channel, or `None` if any channel has no matching position.
+pub(crate) fn positions(
+    types: &[sys::AUDIO_CHANNEL_TYPE],
+    indices: &[u8],
+) -> Option<Vec<Position>> {
+    let count = |ty| types.iter().filter(|t| **t == ty).count();
+    let positions = types
+        .iter()
+        .zip(indices)
+        .map(|(ty, idx)| {
+            let idx = *idx as usize;
+            match *ty {
+                sys::AUDIO_CHANNEL_TYPE_ACT_FRONT => front_position(idx, count(*ty)),
+                sys::AUDIO_CHANNEL_TYPE_ACT_SIDE => {
+                    pair_position(idx, count(*ty), Position::SIDE_LEFT, Position::SIDE_RIGHT)
+                }
+                sys::AUDIO_CHANNEL_TYPE_ACT_BACK => back_position(idx, count(*ty)),
+                sys::AUDIO_CHANNEL_TYPE_ACT_LFE if idx == 0 => Some(Position::LFE1),
+                _ => None,
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    // Every channel needs a distinct position
+    let mask = positions
+        .iter()
+        .fold(Position::empty(), |acc, pos| acc.union(*pos));
+    (mask.bits().count_ones() as usize == positions.len()).then_some(positions)
+}
+
+/// Front channels are numbered from the center outwards, so the last pair is front left and
+/// right.
+fn front_position(idx: usize, count: usize) -> Option<Position> {
+    let (idx, count) = if count % 2 == 1 {
+        if idx == 0 {
+            return Some(Position::FRONT_CENTER);
+        }
+        (idx - 1, count - 1)
+    } else {
+        (idx, count)
+    };
+    let pairs = count / 2;
+    let left = idx % 2 == 0;
+    match pairs.checked_sub(idx / 2 + 1)? {
+        0 if left => Some(Position::FRONT_LEFT),
+        0 => Some(Position::FRONT_RIGHT),
+        1 if left => Some(Position::FRONT_LEFT_CENTER),
+        1 => Some(Position::FRONT_RIGHT_CENTER),
+        _ => None,
+    }
+}
+
+/// Back channels are numbered from the outside inwards, with the center channel last.
+fn back_position(idx: usize, count: usize) -> Option<Position> {
+    if count % 2 == 1 && idx == count - 1 {
+        return Some(Position::REAR_CENTER);
+    }
+    pair_position(idx, count, Position::REAR_LEFT, Position::REAR_RIGHT)
+}
+
+fn pair_position(idx: usize, count: usize, left: Position, right: Position) -> Option<Position> {
+    match idx {
+        0 if count >= 2 => Some(left),
+        1 if count >= 2 => Some(right),
+        _ => None,
+    }
+}
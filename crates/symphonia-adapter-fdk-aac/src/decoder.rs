@@ -0,0 +1,164 @@
+use std::ffi::{c_int, c_uint};
+
+use fdk_aac_sys as sys;
+
+/// Error code returned by FDK.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct DecoderError(sys::AAC_DECODER_ERROR);
+
+impl DecoderError {
+    pub(crate) const TRANSPORT_SYNC_ERROR: Self =
+        Self(sys::AAC_DECODER_ERROR_AAC_DEC_TRANSPORT_SYNC_ERROR);
+    pub(crate) const NOT_ENOUGH_BITS: Self = Self(sys::AAC_DECODER_ERROR_AAC_DEC_NOT_ENOUGH_BITS);
+    pub(crate) const OUT_OF_MEMORY: Self = Self(sys::AAC_DECODER_ERROR_AAC_DEC_OUT_OF_MEMORY);
+
+    pub(crate) fn message(self) -> &'static str {
+        match self.0 {
+            sys::AAC_DECODER_ERROR_AAC_DEC_OUT_OF_MEMORY => "Heap returned NULL pointer.",
+            sys::AAC_DECODER_ERROR_AAC_DEC_TRANSPORT_SYNC_ERROR => {
+                "The transport decoder had synchronization problems."
+            }
+            sys::AAC_DECODER_ERROR_AAC_DEC_NOT_ENOUGH_BITS => "The input buffer ran out of bits.",
+            sys::AAC_DECODER_ERROR_AAC_DEC_INVALID_HANDLE => "The decoder handle is invalid.",
+            sys::AAC_DECODER_ERROR_AAC_DEC_UNSUPPORTED_AOT => {
+                "The AOT found in the configuration is not supported."
+            }
+            sys::AAC_DECODER_ERROR_AAC_DEC_UNSUPPORTED_FORMAT => {
+                "The bitstream format is not supported."
+            }
+            sys::AAC_DECODER_ERROR_AAC_DEC_UNSUPPORTED_ER_FORMAT => {
+                "The error resilience tool format is not supported."
+            }
+            sys::AAC_DECODER_ERROR_AAC_DEC_UNSUPPORTED_EPCONFIG => {
+                "The error protection format is not supported."
+            }
+            sys::AAC_DECODER_ERROR_AAC_DEC_UNSUPPORTED_MULTILAYER => {
+                "More than one layer for AAC scalable is not supported."
+            }
+            sys::AAC_DECODER_ERROR_AAC_DEC_UNSUPPORTED_CHANNELCONFIG => {
+                "The channel configuration is not supported."
+            }
+            sys::AAC_DECODER_ERROR_AAC_DEC_UNSUPPORTED_SAMPLINGRATE => {
+                "The sample rate specified in the configuration is not supported."
+            }
+            sys::AAC_DECODER_ERROR_AAC_DEC_INVALID_SBR_CONFIG => {
+                "The SBR configuration is not supported."
+            }
+            sys::AAC_DECODER_ERROR_AAC_DEC_SET_PARAM_FAIL => "The parameter could not be set.",
+            sys::AAC_DECODER_ERROR_AAC_DEC_NEED_TO_RESTART => {
+                "The decoder needs to be restarted to change its configuration."
+            }
+            sys::AAC_DECODER_ERROR_AAC_DEC_OUTPUT_BUFFER_TOO_SMALL => {
+                "The provided output buffer is too small."
+            }
+            sys::AAC_DECODER_ERROR_AAC_DEC_TRANSPORT_ERROR => {
+                "The transport decoder encountered an unexpected error."
+            }
+            sys::AAC_DECODER_ERROR_AAC_DEC_PARSE_ERROR => "Error while parsing the bitstream.",
+            sys::AAC_DECODER_ERROR_AAC_DEC_UNSUPPORTED_EXTENSION_PAYLOAD => {
+                "The extension payload type is not supported."
+            }
+            sys::AAC_DECODER_ERROR_AAC_DEC_DECODE_FRAME_ERROR => {
+                "The parsed bitstream value is out of range."
+            }
+            sys::AAC_DECODER_ERROR_AAC_DEC_CRC_ERROR => "The embedded CRC did not match.",
+            sys::AAC_DECODER_ERROR_AAC_DEC_INVALID_CODE_BOOK => "An invalid codebook was signaled.",
+            _ => "Unknown error",
+        }
+    }
+}
+
+fn check(code: sys::AAC_DECODER_ERROR) -> Result<(), DecoderError> {
+    match code {
+        sys::AAC_DECODER_ERROR_AAC_DEC_OK => Ok(()),
+        code => Err(DecoderError(code)),
+    }
+}
+
+/// Bitstream format of the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Transport {
+    /// Raw access units configured by an AudioSpecificConfig.
+    Raw,
+    Adts,
+    /// LATM AudioMuxElements in a LOAS AudioSyncStream, configured in-band by a StreamMuxConfig.
+    Loas,
+}
+
+/// Thin wrapper around the FDK decoder handle.
+#[derive(Debug)]
+pub(crate) struct Decoder {
+    handle: sys::HANDLE_AACDECODER,
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        unsafe {
+            sys::aacDecoder_Close(self.handle);
+        }
+    }
+}
+
+unsafe impl Send for Decoder {}
+unsafe impl Sync for Decoder {}
+
+impl Decoder {
+    /// Opens a decoder for the given transport. FDK only fails to open one if it runs out of
+    /// memory.
+    pub(crate) fn new(transport: Transport) -> Result<Self, DecoderError> {
+        let transport = match transport {
+            Transport::Raw => sys::TRANSPORT_TYPE_TT_MP4_RAW,
+            Transport::Adts => sys::TRANSPORT_TYPE_TT_MP4_ADTS,
+            Transport::Loas => sys::TRANSPORT_TYPE_TT_MP4_LOAS,
+        };
+        let handle = unsafe { sys::aacDecoder_Open(transport, 1) };
+        if handle.is_null() {
+            return Err(DecoderError::OUT_OF_MEMORY);
+        }
+        Ok(Self { handle })
+    }
+
+    pub(crate) fn config_raw(&mut self, config: &[u8]) -> Result<(), DecoderError> {
+        let mut ptr = config.as_ptr().cast_mut();
+        let len = config.len() as c_uint;
+        check(unsafe { sys::aacDecoder_ConfigRaw(self.handle, &mut ptr, &len) })
+    }
+
+    pub(crate) fn set_param(
+        &mut self,
+        param: sys::AACDEC_PARAM,
+        value: c_int,
+    ) -> Result<(), DecoderError> {
+        check(unsafe { sys::aacDecoder_SetParam(self.handle, param, value) })
+    }
+
+    /// Copies as much of `data` as fits into the internal buffer and returns the number of bytes
+    /// copied.
+    pub(crate) fn fill(&mut self, data: &[u8]) -> Result<usize, DecoderError> {
+        let mut ptr = data.as_ptr().cast_mut();
+        let len = data.len() as c_uint;
+        let mut bytes_valid = len;
+        check(unsafe { sys::aacDecoder_Fill(self.handle, &mut ptr, &len, &mut bytes_valid) })?;
+        Ok(data.len() - bytes_valid as usize)
+    }
+
+    /// Decodes a frame with the given `AACDEC_*` flags.
+    pub(crate) fn decode_frame(&mut self, pcm: &mut [i16], flags: u32) -> Result<(), DecoderError> {
+        check(unsafe {
+            sys::aacDecoder_DecodeFrame(self.handle, pcm.as_mut_ptr(), pcm.len() as c_int, flags)
+        })
+    }
+
+    /// Number of interleaved samples in the last decoded frame.
+    pub(crate) fn decoded_frame_size(&self) -> usize {
+        self.stream_info().map_or(0, |stream_info| {
+            stream_info.numChannels.max(0) as usize * stream_info.frameSize.max(0) as usize
+        })
+    }
+
+    /// Returns the stream info, which FDK keeps up to date with every decoded frame. This is
+    /// `None` if FDK doesn't provide one.
+    pub(crate) fn stream_info(&self) -> Option<&sys::CStreamInfo> {
+        unsafe { sys::aacDecoder_GetStreamInfo(self.handle).as_ref() }
+    }
+}
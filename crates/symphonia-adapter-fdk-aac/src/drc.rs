@@ -0,0 +1,155 @@
+use fdk_aac_sys as sys;
+use symphonia_core::errors::{Error, Result};
+
+use crate::decoder::Decoder;
+
+/// MPEG-D DRC effect to request from the stream.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DrcEffectType {
+    /// Disable MPEG-D DRC completely.
+    Off,
+    /// Disable MPEG-D DRC, except where it's needed to prevent clipping.
+    None,
+    /// Late night listening.
+    LateNight,
+    /// Listening in a noisy environment.
+    NoisyEnvironment,
+    /// Playback with a limited dynamic range.
+    LimitedPlaybackRange,
+    /// Playback at a low level.
+    LowPlaybackLevel,
+    /// Make dialog easier to understand.
+    DialogEnhancement,
+    /// Compression without any particular goal.
+    GeneralCompression,
+}
+
+impl DrcEffectType {
+    fn value(self) -> i32 {
+        match self {
+            Self::Off => -1,
+            Self::None => 0,
+            Self::LateNight => 1,
+            Self::NoisyEnvironment => 2,
+            Self::LimitedPlaybackRange => 3,
+            Self::LowPlaybackLevel => 4,
+            Self::DialogEnhancement => 5,
+            Self::GeneralCompression => 6,
+        }
+    }
+}
+
+/// Loudness normalization and dynamic range control (DRC) settings. These apply to both MPEG-4
+/// DRC and MPEG-D DRC unless noted otherwise. Settings that aren't set use FDK's defaults.
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DrcOptions {
+    /// Level to normalize the output to, in steps of 0.25 dB below full scale. Valid values range
+    /// from 40 (-10 dBFS) to 127 (-31.75 dBFS). Negative values disable loudness normalization.
+    pub target_reference_level: Option<i8>,
+    /// How much of the boost factors from the stream to apply, from 0 (none) to 127 (all).
+    pub boost_factor: Option<u8>,
+    /// How much of the attenuation factors from the stream to apply, from 0 (none) to 127 (all).
+    pub attenuation_factor: Option<u8>,
+    /// Apply DVB heavy compression instead of MPEG-4 DRC.
+    pub heavy_compression: Option<bool>,
+    /// MPEG-D DRC effect to apply. MPEG-D DRC is mainly used by USAC streams, which carry their
+    /// loudness info in the AudioSpecificConfig.
+    pub effect_type: Option<DrcEffectType>,
+    /// Use the album loudness instead of the track loudness for MPEG-D DRC.
+    pub album_mode: Option<bool>,
+}
+
+impl DrcOptions {
+    /// Set the target reference level.
+    pub fn target_reference_level(mut self, level: i8) -> Self {
+        self.target_reference_level = Some(level);
+        self
+    }
+
+    /// Set the boost factor.
+    pub fn boost_factor(mut self, factor: u8) -> Self {
+        self.boost_factor = Some(factor);
+        self
+    }
+
+    /// Set the attenuation factor.
+    pub fn attenuation_factor(mut self, factor: u8) -> Self {
+        self.attenuation_factor = Some(factor);
+        self
+    }
+
+    /// Enable or disable heavy compression.
+    pub fn heavy_compression(mut self, heavy_compression: bool) -> Self {
+        self.heavy_compression = Some(heavy_compression);
+        self
+    }
+
+    /// Set the MPEG-D DRC effect type.
+    pub fn effect_type(mut self, effect_type: DrcEffectType) -> Self {
+        self.effect_type = Some(effect_type);
+        self
+    }
+
+    /// Enable or disable album mode.
+    pub fn album_mode(mut self, album_mode: bool) -> Self {
+        self.album_mode = Some(album_mode);
+        self
+    }
+
+    pub(crate) fn apply(&self, decoder: &mut Decoder) -> Result<()> {
+        let params = [
+            (
+                sys::AACDEC_PARAM_AAC_DRC_REFERENCE_LEVEL,
+                self.target_reference_level.map(i32::from),
+            ),
+            (
+                sys::AACDEC_PARAM_AAC_DRC_BOOST_FACTOR,
+                self.boost_factor.map(i32::from),
+            ),
+            (
+                sys::AACDEC_PARAM_AAC_DRC_ATTENUATION_FACTOR,
+                self.attenuation_factor.map(i32::from),
+            ),
+            (
+                sys::AACDEC_PARAM_AAC_DRC_HEAVY_COMPRESSION,
+                self.heavy_compression.map(i32::from),
+            ),
+            (
+                sys::AACDEC_PARAM_AAC_UNIDRC_SET_EFFECT,
+                self.effect_type.map(DrcEffectType::value),
+            ),
+            (
+                sys::AACDEC_PARAM_AAC_UNIDRC_ALBUM_MODE,
+                self.album_mode.map(i32::from),
+            ),
+        ];
+        for (param, value) in params {
+            if let Some(value) = value {
+                decoder
+                    .set_param(param, value)
+                    .map_err(|e| Error::DecodeError(e.message()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Loudness and DRC metadata found in the stream.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DrcInfo {
+    /// Average loudness of the program from the MPEG-4 DRC metadata, in steps of 0.25 dB below
+    /// full scale.
+    pub program_reference_level: Option<u8>,
+    /// Whether the stream contains DRC data.
+    pub drc_present: bool,
+}
+
+impl DrcInfo {
+    pub(crate) fn from_stream_info(info: &sys::CStreamInfo) -> Self {
+        Self {
+            program_reference_level: u8::try_from(info.drcProgRefLev).ok(),
+            drc_present: info.flags & sys::AC_DRC_PRESENT != 0,
+        }
+    }
+}
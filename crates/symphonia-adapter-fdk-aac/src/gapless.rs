@@ -0,0 +1,26 @@
+/// Encoder delay and padding of a stream, in samples per channel at the output sample rate.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GaplessInfo {
+    /// Priming samples added by the encoder at the start of the stream.
+    pub delay: u32,
+    /// Samples added by the encoder to fill out the last frame.
+    pub padding: u32,
+    /// Length of the original audio, without delay or padding.
+    pub samples: u64,
+}
+
+impl GaplessInfo {
+    /// Parses the value of an iTunes `iTunSMPB` tag.
+    ///
+    /// The tag is a list of hex numbers, where the second, third and fourth ones are the delay,
+    /// padding and original length. Returns `None` if any of them are missing or invalid.
+    pub fn from_itunsmpb(value: &str) -> Option<Self> {
+        let mut fields = value.split_whitespace().skip(1);
+        let mut next = || u64::from_str_radix(fields.next()?, 16).ok();
+        Some(Self {
+            delay: next()?.try_into().ok()?,
+            padding: next()?.try_into().ok()?,
+            samples: next()?,
+        })
+    }
+}
@@ -4,32 +4,53 @@
 #![doc = include_str!("../README.md")]
 
 mod adts;
-mod meta;
+mod channels;
+mod decoder;
+mod drc;
+mod gapless;
+mod loas;
+pub mod meta;
+mod stream_info;
+mod trim;
 
+use std::collections::VecDeque;
 use std::fmt;
 
-use fdk_aac::dec::{Decoder, DecoderError, Transport};
-use log::warn;
+use fdk_aac_sys as sys;
+use log::{info, warn};
 use symphonia_core::audio::{
-    AsGenericAudioBufferRef, AudioBuffer, AudioMut, AudioSpec, GenericAudioBufferRef,
+    AsGenericAudioBufferRef, AudioBuffer, AudioMut, AudioSpec, GenericAudioBufferRef, layouts,
 };
-use symphonia_core::codecs::CodecInfo;
 use symphonia_core::codecs::audio::well_known::CODEC_ID_AAC;
 use symphonia_core::codecs::audio::well_known::profiles::{
-    CODEC_PROFILE_AAC_HE, CODEC_PROFILE_AAC_HE_V2, CODEC_PROFILE_AAC_LC,
+    CODEC_PROFILE_AAC_HE, CODEC_PROFILE_AAC_HE_V2, CODEC_PROFILE_AAC_LC, CODEC_PROFILE_AAC_LTP,
+    CODEC_PROFILE_AAC_MAIN, CODEC_PROFILE_AAC_SSR, CODEC_PROFILE_AAC_USAC,
 };
 use symphonia_core::codecs::audio::{
-    AudioCodecParameters, AudioDecoder, AudioDecoderOptions, FinalizeResult,
+    AudioCodecId, AudioCodecParameters, AudioDecoder, AudioDecoderOptions, FinalizeResult,
 };
 use symphonia_core::codecs::registry::{RegisterableAudioDecoder, SupportedAudioCodec};
-use symphonia_core::errors::{Error, unsupported_error};
-use symphonia_core::io::FiniteStream;
+use symphonia_core::codecs::{CodecInfo, CodecProfile};
+use symphonia_core::common::FourCc;
+use symphonia_core::errors::{Error, decode_error, unsupported_error};
 use symphonia_core::packet::PacketRef;
 use symphonia_core::{codec_profile, support_audio_codec};
 
-use crate::adts::construct_adts_header;
-use crate::macros::validate;
-use crate::meta::{M4A_TYPES, M4AInfo, M4AType, map_to_channels, sample_rate_index};
+use crate::adts::{
+    AdtsConfig, AdtsFrame, construct_adts_header, has_adts_header, split_adts_frames,
+};
+use crate::channels::ChannelLayout;
+use crate::decoder::{Decoder, DecoderError, Transport};
+pub use crate::drc::{DrcEffectType, DrcInfo, DrcOptions};
+pub use crate::gapless::GaplessInfo;
+use crate::loas::has_loas_header;
+pub use crate::loas::{FORMAT_ID_LOAS, LoasReader};
+use crate::meta::{
+    AudioSpecificConfig, M4A_TYPES, M4AInfo, M4AType, map_to_channels, sample_rate_index,
+    unwrap_esds,
+};
+pub use crate::stream_info::AacStreamInfo;
+use crate::trim::{PendingPacket, TrimQueue};
 
 type Result<T> = symphonia_core::errors::Result<T>;
 
@@ -45,97 +66,688 @@ mod macros {
     pub(crate) use validate;
 }
 
-const MAX_SAMPLES: usize = 8192;
+/// FDK outputs at most 8 channels.
+const MAX_CHANNELS: usize = 8;
+/// USAC with 4:1 SBR outputs the longest frames.
+const MAX_FRAME_SIZE: usize = 4096;
+/// Encoder delay of HE-AAC streams without gapless info, as used by Apple's and FDK's encoders.
+const HE_AAC_PRIMING: u64 = 2112;
+
+/// AAC framed as LATM AudioMuxElements in a LOAS AudioSyncStream, as produced by [`LoasReader`].
+/// Packets of this codec are whole LOAS frames, including the sync word.
+pub const CODEC_ID_AAC_LATM: AudioCodecId = AudioCodecId::new(FourCc::new(*b"latm"));
+
+/// Start of the AAC profile codes defined by this crate for profiles that Symphonia doesn't have.
+/// Symphonia numbers its AAC profiles by object type, which keeps them far below this range.
+pub const CODEC_PROFILE_AAC_ADAPTER_BASE: u32 = 0x8000_0000;
+/// AAC-LD (Low Delay) profile. This is specific to this crate, see
+/// [`CODEC_PROFILE_AAC_ADAPTER_BASE`].
+pub const CODEC_PROFILE_AAC_LD: CodecProfile = CodecProfile::new(CODEC_PROFILE_AAC_ADAPTER_BASE);
+/// AAC-ELD (Enhanced Low Delay) profile. This is specific to this crate, see
+/// [`CODEC_PROFILE_AAC_ADAPTER_BASE`].
+pub const CODEC_PROFILE_AAC_ELD: CodecProfile =
+    CodecProfile::new(CODEC_PROFILE_AAC_ADAPTER_BASE + 1);
+
+/// Additional options for [`AacDecoder`].
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AacDecoderOptions {
+    /// Trim the encoder delay and padding from packets that don't specify any trims, as is the
+    /// case for ADTS streams and MP4 files without an edit list. The delay and padding come
+    /// from `gapless_info` if set. Otherwise, the usual priming for the profile is trimmed from
+    /// the start: one core frame for AAC-LC and the low delay profiles, and 2112 samples when SBR
+    /// is present. Has no effect if gapless playback is disabled in the
+    /// [`AudioDecoderOptions`].
+    pub auto_trim: bool,
+    /// Gapless info from the container, typically parsed from an `iTunSMPB` tag with
+    /// [`GaplessInfo::from_itunsmpb`].
+    pub gapless_info: Option<GaplessInfo>,
+    /// Loudness normalization and dynamic range control settings.
+    pub drc: DrcOptions,
+}
+
+impl AacDecoderOptions {
+    /// Enable or disable automatic trimming of encoder delay and padding.
+    pub fn auto_trim(mut self, auto_trim: bool) -> Self {
+        self.auto_trim = auto_trim;
+        self
+    }
+
+    /// Set the gapless info to use for automatic trimming.
+    pub fn gapless_info(mut self, gapless_info: GaplessInfo) -> Self {
+        self.gapless_info = Some(gapless_info);
+        self
+    }
+
+    /// Set the loudness normalization and dynamic range control settings.
+    pub fn drc(mut self, drc: DrcOptions) -> Self {
+        self.drc = drc;
+        self
+    }
+}
 
 /// Symphonia-compatible wrapper for the FDK AAC decoder.
+///
+/// FDK delays its output, so the end of the stream is still buffered in the decoder after the
+/// last packet. Call [`drain`](Self::drain) or [`finalize`](AudioDecoder::finalize) to get it.
+/// When gapless playback is enabled, the delay is removed from the start of the stream so that the
+/// output lines up with the packet timestamps.
 pub struct AacDecoder {
     decoder: Decoder,
+    transport: Transport,
+    /// Set until the first packet when the extra data isn't usable, since the config then has to
+    /// come from the ADTS or LOAS headers of the packets.
+    detect_transport: bool,
+    /// Header fields of the ADTS frames that are unwrapped when the transport is raw.
+    adts_config: Option<AdtsConfig>,
     buf: AudioBuffer<i16>,
     codec_params: AudioCodecParameters,
     m4a_info: M4AInfo,
     m4a_info_validated: bool,
-    pcm: [i16; MAX_SAMPLES],
+    /// Set when the stream config changes while decoding a packet.
+    config_changed: bool,
+    layout: ChannelLayout,
+    /// Whether SBR or PS was present in the last decoded frame.
+    sbr: bool,
+    /// Set after a reset until the next frame is decoded.
+    reset_pending: bool,
+    /// Set after a reset until audio is output again, which is then faded in.
+    fade_in: bool,
+    gapless: bool,
+    aac_opts: AacDecoderOptions,
+    trim: TrimQueue,
+    /// Packets that were passed to the decoder but haven't been output yet. FDK holds back the
+    /// start of LATM streams until enough data is buffered, so this can be more than one.
+    pending: VecDeque<PendingPacket>,
+    pcm: Vec<i16>,
+    /// Samples of the current packet that are kept.
+    samples: Vec<i16>,
 }
 
 impl fmt::Debug for AacDecoder {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("AacDecoder")
             .field("decoder", &self.decoder)
+            .field("transport", &self.transport)
+            .field("detect_transport", &self.detect_transport)
+            .field("adts_config", &self.adts_config)
             .field("buf", &"<buf>")
             .field("codec_params", &self.codec_params)
             .field("m4a_info", &self.m4a_info)
             .field("m4a_info_validated", &self.m4a_info_validated)
+            .field("config_changed", &self.config_changed)
+            .field("layout", &self.layout)
+            .field("sbr", &self.sbr)
+            .field("reset_pending", &self.reset_pending)
+            .field("fade_in", &self.fade_in)
+            .field("gapless", &self.gapless)
+            .field("aac_opts", &self.aac_opts)
+            .field("trim", &self.trim)
+            .field("pending", &self.pending)
             .field("pcm", &"<pcm>")
+            .field("samples", &"<samples>")
             .finish()
     }
 }
 
 impl AacDecoder {
-    fn try_new(params: &AudioCodecParameters, _opts: &AudioDecoderOptions) -> Result<Self> {
-        let mut m4a_info = M4AInfo::default();
-        if let Some(extra_data_buf) = &params.extra_data {
-            validate!(extra_data_buf.len() >= 2);
-            m4a_info.read(extra_data_buf)?;
+    fn try_new(params: &AudioCodecParameters, opts: &AudioDecoderOptions) -> Result<Self> {
+        Self::try_new_with_options(params, opts, &AacDecoderOptions::default())
+    }
+
+    /// Creates a decoder with additional AAC-specific options.
+    pub fn try_new_with_options(
+        params: &AudioCodecParameters,
+        opts: &AudioDecoderOptions,
+        aac_opts: &AacDecoderOptions,
+    ) -> Result<Self> {
+        let mut detect_transport = false;
+        let (mut decoder, transport, m4a_info, adts_config) = if params.codec == CODEC_ID_AAC_LATM {
+            // Each LOAS frame can carry a StreamMuxConfig, so FDK configures itself in-band
+            let m4a_info = m4a_info_from_params(params);
+            (
+                new_decoder(Transport::Loas)?,
+                Transport::Loas,
+                m4a_info,
+                None,
+            )
+        } else if let Some(extra_data) = params.extra_data.as_deref() {
+            match raw_decoder(extra_data)? {
+                Some((decoder, m4a_info, adts_config)) => {
+                    (decoder, Transport::Raw, m4a_info, adts_config)
+                }
+                // The transport is replaced once the first packet shows which one is used
+                None => {
+                    detect_transport = true;
+                    (
+                        new_decoder(Transport::Adts)?,
+                        Transport::Adts,
+                        M4AInfo::default(),
+                        None,
+                    )
+                }
+            }
         } else {
-            m4a_info.otype = M4AType::Lc;
-            m4a_info.sample_rate = params.sample_rate.unwrap_or_default();
-            m4a_info.sample_rate_index = sample_rate_index(m4a_info.sample_rate);
-
-            m4a_info.channels = if let Some(channels) = &params.channels {
-                channels.count() as u8
-            } else {
-                return unsupported_error("aac: channels or channel layout is required");
-            };
-        }
-        let decoder = Decoder::new(Transport::Adts);
+            // ADTS headers can only signal the profiles up to LTP
+            if matches!(
+                params.profile,
+                Some(CODEC_PROFILE_AAC_LD | CODEC_PROFILE_AAC_ELD | CODEC_PROFILE_AAC_USAC)
+            ) {
+                return unsupported_error("aac: profile requires an audio specific config");
+            }
+            let m4a_info = m4a_info_from_params(params);
+            // Without a config, FDK needs an ADTS header on each packet to configure itself.
+            // Packets that already have one can be decoded even if the parameters are missing.
+            (
+                new_decoder(Transport::Adts)?,
+                Transport::Adts,
+                m4a_info,
+                None,
+            )
+        };
+        aac_opts.drc.apply(&mut decoder)?;
 
-        let buf = audio_buffer(&m4a_info, m4a_info.sample_rate)?;
+        // This is only a guess until the first frame is decoded, so fall back to the container's
+        // layout, or to stereo if the config has to come from the packets.
+        let channels = m4a_info
+            .layout
+            .clone()
+            .or_else(|| map_to_channels(m4a_info.channels))
+            .or_else(|| params.channels.clone())
+            .unwrap_or(layouts::CHANNEL_LAYOUT_STEREO);
+        let layout = ChannelLayout {
+            order: (0..channels.count()).collect(),
+            channels,
+        };
+        let buf = audio_buffer(&layout, m4a_info.sample_rate, m4a_info.samples)?;
         Ok(Self {
             decoder,
+            transport,
+            detect_transport,
+            adts_config,
             codec_params: params.clone(),
             buf,
             m4a_info,
             // We should always prefer the m4a info from the decoder even if we were able to parse
             // the extra data from the header since it could be more accurate
             m4a_info_validated: false,
-            pcm: [0; _],
+            config_changed: false,
+            layout,
+            sbr: false,
+            reset_pending: false,
+            fade_in: false,
+            gapless: opts.gapless,
+            aac_opts: *aac_opts,
+            trim: TrimQueue::default(),
+            pending: VecDeque::new(),
+            pcm: vec![0; MAX_CHANNELS * MAX_FRAME_SIZE],
+            samples: Vec::new(),
         })
     }
 
+    /// Updates the output format from the stream info, which can change mid-stream when the
+    /// config is signaled in-band.
     fn configure_metadata(&mut self) -> Result<()> {
-        let stream_info = self.decoder.stream_info();
+        let Some(stream_info) = self.decoder.stream_info() else {
+            return decode_error("aac: missing stream info");
+        };
         let capacity = self.decoder.decoded_frame_size();
         let channels = stream_info.numChannels as usize;
-        let sample_rate = stream_info.aacSampleRate as u32;
+        if channels == 0 {
+            return decode_error("aac: stream info has no channels");
+        }
 
-        self.m4a_info = M4AInfo {
-            otype: M4A_TYPES[stream_info.aot as usize],
+        let m4a_info = M4AInfo {
+            otype: M4A_TYPES
+                .get(stream_info.aot as usize)
+                .copied()
+                .unwrap_or(M4AType::Unknown),
             channels: stream_info.numChannels as u8,
-            sample_rate,
-            sample_rate_index: sample_rate_index(sample_rate),
+            sample_rate: stream_info.sampleRate as u32,
+            sample_rate_index: sample_rate_index(stream_info.aacSampleRate as u32),
+            layout: None,
             samples: capacity / channels,
         };
+        let layout = ChannelLayout::from_stream_info(stream_info);
+        // PS is only detected once the decoder has seen it in the stream, which can take a few
+        // frames, so the profile is kept up to date separately from the output format.
+        if let Some(profile) = codec_profile(stream_info) {
+            self.codec_params.with_profile(profile);
+        }
+        let sbr = stream_info.flags & (sys::AC_SBR_PRESENT | sys::AC_PS_PRESENT) != 0;
+        if self.m4a_info_validated
+            && m4a_info.sample_rate == self.m4a_info.sample_rate
+            && m4a_info.channels == self.m4a_info.channels
+            && layout == self.layout
+            && sbr == self.sbr
+        {
+            // USAC varies the frame length from frame to frame, which doesn't change the format
+            let samples = m4a_info.samples as u64;
+            if self.codec_params.frames_per_block < Some(samples) {
+                self.buf.grow_capacity(m4a_info.samples);
+                self.codec_params.with_frames_per_block(samples);
+            }
+            self.m4a_info = m4a_info;
+            return Ok(());
+        }
 
-        self.buf = audio_buffer(&self.m4a_info, stream_info.sampleRate as u32)?;
+        self.buf = audio_buffer(&layout, m4a_info.sample_rate, m4a_info.samples)?;
+        if self.m4a_info_validated {
+            info!("aac: stream changed from {} to {m4a_info}", self.m4a_info);
+            // The samples decoded so far don't match the new format
+            if !self.samples.is_empty() {
+                warn!("aac: dropping audio decoded before the stream changed");
+                self.samples.clear();
+            }
+            self.config_changed = true;
+        } else {
+            self.reset_trim();
+        }
+        self.codec_params
+            .with_sample_rate(m4a_info.sample_rate)
+            .with_channels(layout.channels.clone())
+            .with_frames_per_block(m4a_info.samples as u64);
+        self.m4a_info = m4a_info;
+        self.layout = layout;
+        self.sbr = sbr;
         self.m4a_info_validated = true;
 
         Ok(())
     }
+
+    /// Details of the stream, updated with every decoded frame. This is only available once a
+    /// packet has been decoded.
+    pub fn stream_info(&self) -> Option<AacStreamInfo> {
+        self.decoder
+            .stream_info()
+            .filter(|_| self.m4a_info_validated)
+            .map(AacStreamInfo::from_stream_info)
+    }
+
+    /// Returns `true` if the sample rate, channels or SBR presence of the stream changed while
+    /// decoding the last packet. The decoded buffer is already in the new format. Changes in
+    /// frame length alone aren't reported.
+    pub fn config_changed(&self) -> bool {
+        self.config_changed
+    }
+
+    /// Loudness and DRC metadata of the stream. This is only available once a packet has been
+    /// decoded.
+    pub fn drc_info(&self) -> Option<DrcInfo> {
+        self.decoder
+            .stream_info()
+            .filter(|_| self.m4a_info_validated)
+            .map(DrcInfo::from_stream_info)
+    }
+
+    /// Returns the number of samples to trim from the start and end of a packet with `frames`
+    /// samples of decoded audio.
+    fn packet_trim(&self, packet: &PendingPacket, frames: usize) -> (usize, usize) {
+        let trim = (packet.trim_start, packet.trim_end);
+        if !self.aac_opts.auto_trim || trim != (0, 0) {
+            return trim;
+        }
+
+        // Timestamps are in the container's time base, which may not match the output sample
+        // rate, so they're scaled by the packet duration.
+        let frames = frames as u64;
+        let pts = packet.pts.max(0) as u64;
+        let start = match packet.dur {
+            0 => pts,
+            dur => pts * frames / dur,
+        };
+        let end = start + frames;
+        let (delay, samples) = match self.aac_opts.gapless_info {
+            Some(info) => (info.delay as u64, Some(info.samples)),
+            None => (self.default_priming(), None),
+        };
+
+        let trim_start = delay.saturating_sub(start).min(frames);
+        let trim_end = samples
+            .map_or(0, |samples| end.saturating_sub(delay + samples))
+            .min(frames - trim_start);
+        (trim_start as usize, trim_end as usize)
+    }
+
+    /// Returns the encoder delay to assume when there's no gapless info, in samples per channel at
+    /// the output sample rate.
+    fn default_priming(&self) -> u64 {
+        let Some(stream_info) = self.decoder.stream_info() else {
+            return 0;
+        };
+        if stream_info.flags & (sys::AC_SBR_PRESENT | sys::AC_PS_PRESENT) != 0 {
+            return HE_AAC_PRIMING;
+        }
+        // Encoders prime the stream with one frame of silence, which is scaled to the output rate
+        // in case the decoder resamples.
+        let frame = stream_info.aacSamplesPerFrame.max(0) as u64;
+        match stream_info.aacSampleRate.max(0) as u64 {
+            0 => frame,
+            rate => frame * stream_info.sampleRate.max(0) as u64 / rate,
+        }
+    }
+
+    /// Queues the trims of the oldest pending packet for a decoded frame with `frames` samples.
+    fn push_trim(&mut self, frames: usize) {
+        match self.pending.pop_front() {
+            Some(packet) if self.gapless => {
+                let (trim_start, trim_end) = self.packet_trim(&packet, frames);
+                self.trim.push_packet(frames, trim_start, trim_end);
+            }
+            _ => self.trim.push(true, frames),
+        }
+    }
+
+    /// Starts tracking the output of the decoder from the beginning. Any audio that comes out
+    /// before the first packet is only kept when gapless playback is disabled.
+    fn reset_trim(&mut self) {
+        let delay = self
+            .decoder
+            .stream_info()
+            .map_or(0, |stream_info| stream_info.outputDelay as usize);
+        self.trim.reset(delay, !self.gapless);
+    }
+
+    /// Picks the transport from the header of the first packet when there's no usable config.
+    fn detect_transport(&mut self, data: &[u8]) -> Result<()> {
+        if has_loas_header(data) {
+            let mut decoder = new_decoder(Transport::Loas)?;
+            self.aac_opts.drc.apply(&mut decoder)?;
+            self.decoder = decoder;
+            self.transport = Transport::Loas;
+        } else if !has_adts_header(data) {
+            return unsupported_error("aac: packets need adts or loas headers without a config");
+        }
+        self.detect_transport = false;
+        Ok(())
+    }
+
+    /// Returns the ADTS frames in the packet, or `None` if it should be decoded as is.
+    fn adts_frames<'a>(&self, data: &'a [u8]) -> Result<Option<Vec<AdtsFrame<'a>>>> {
+        if !has_adts_header(data) {
+            return Ok(None);
+        }
+        match self.transport {
+            Transport::Adts => Ok(Some(split_adts_frames(data)?)),
+            // Raw access units can start with a sync word by chance, so the frames are only
+            // unwrapped if every header matches the config
+            Transport::Raw => Ok(self.adts_config.and_then(|config| {
+                split_adts_frames(data)
+                    .ok()
+                    .filter(|frames| frames.iter().all(|frame| frame.config == config))
+            })),
+            Transport::Loas => Ok(None),
+        }
+    }
+
+    /// Passes a packet to the decoder and appends the samples to keep to `samples`.
+    fn decode_frame(&mut self, mut data: &[u8], packet: PendingPacket) -> Result<()> {
+        self.pending.push_back(packet);
+
+        // The internal buffer may not have room for all of the data while LATM frames are held
+        // back, so the rest is filled in as decoding frees up space. A LATM packet can also hold
+        // several frames, which are all decoded once the whole packet is buffered.
+        loop {
+            let consumed = match self.decoder.fill(data) {
+                Ok(consumed) => consumed,
+                Err(e) => {
+                    self.pending.pop_back();
+                    return Err(Error::DecodeError(e.message()));
+                }
+            };
+            data = &data[consumed..];
+            let decoded = self.decode_buffered()?;
+            if data.is_empty() {
+                if decoded && self.transport == Transport::Loas {
+                    continue;
+                }
+                return Ok(());
+            }
+            if consumed == 0 && !decoded {
+                self.pending.pop_back();
+                return decode_error("aac: decoder input buffer is full");
+            }
+        }
+    }
+
+    /// Decodes a frame from the data buffered in the decoder. Returns `false` if there wasn't a
+    /// complete frame to decode.
+    fn decode_buffered(&mut self) -> Result<bool> {
+        let flags = if self.reset_pending {
+            sys::AACDEC_CLRHIST | sys::AACDEC_INTR
+        } else {
+            0
+        };
+        match self.decoder.decode_frame(&mut self.pcm, flags) {
+            Ok(_) => {}
+            Err(e @ DecoderError::TRANSPORT_SYNC_ERROR) => {
+                warn!("aac: transport sync error: {}", e.message());
+                self.pending.pop_back();
+                return Ok(false);
+            }
+            // LATM packets stay buffered until the decoder has enough data to start decoding
+            Err(DecoderError::NOT_ENOUGH_BITS) if self.transport == Transport::Loas => {
+                return Ok(false);
+            }
+            Err(e) => {
+                self.pending.pop_back();
+                return Err(Error::DecodeError(e.message()));
+            }
+        }
+        self.reset_pending = false;
+        self.configure_metadata()?;
+
+        let num_channels = self.layout.order.len();
+        let frames = self.decoder.decoded_frame_size() / num_channels;
+        self.push_trim(frames);
+        let (start, end) = self.trim.pop(frames);
+        let kept = &mut self.pcm[start * num_channels..(frames - end) * num_channels];
+        if self.fade_in && !kept.is_empty() {
+            // The first frame after clearing the history has nothing to overlap with, so it
+            // would be heard as a click. Fading it in keeps the output sample-accurate.
+            fade_in(kept, num_channels);
+            self.fade_in = false;
+        }
+        self.samples.extend_from_slice(kept);
+        Ok(true)
+    }
+
+    /// Flushes the audio that's still buffered in the decoder at the end of the stream.
+    ///
+    /// This is also called by [`finalize`](AudioDecoder::finalize), after which the audio is
+    /// available from [`last_decoded`](AudioDecoder::last_decoded). The decoder should be reset
+    /// before decoding more packets.
+    pub fn drain(&mut self) -> Result<GenericAudioBufferRef<'_>> {
+        self.buf.clear();
+        if !self.m4a_info_validated {
+            // Nothing has been decoded yet
+            return Ok(self.buf.as_generic_audio_buffer_ref());
+        }
+
+        let num_channels = self.layout.order.len();
+        let mut drained = Vec::new();
+        // Decode the packets that are still buffered before flushing the delay
+        while !self.pending.is_empty() {
+            match self.decoder.decode_frame(&mut self.pcm, 0) {
+                Ok(()) => {}
+                Err(DecoderError::NOT_ENOUGH_BITS) => break,
+                Err(e) => return Err(Error::DecodeError(e.message())),
+            }
+            let frames = self.decoder.decoded_frame_size() / num_channels;
+            self.push_trim(frames);
+            let (start, end) = self.trim.pop(frames);
+            drained
+                .extend_from_slice(&self.pcm[start * num_channels..(frames - end) * num_channels]);
+        }
+        self.pending.clear();
+
+        while !self.trim.is_empty() {
+            self.decoder
+                .decode_frame(&mut self.pcm, sys::AACDEC_FLUSH)
+                .map_err(|e| Error::DecodeError(e.message()))?;
+            let frames = self.decoder.decoded_frame_size() / num_channels;
+            if frames == 0 {
+                break;
+            }
+            let (start, end) = self.trim.pop(frames);
+            drained
+                .extend_from_slice(&self.pcm[start * num_channels..(frames - end) * num_channels]);
+        }
+
+        let frames = drained.len() / num_channels;
+        self.buf.grow_capacity(frames);
+        self.buf.render_uninit(Some(frames));
+        copy_interleaved(&mut self.buf, &self.layout, &drained);
+        Ok(self.buf.as_generic_audio_buffer_ref())
+    }
+}
+
+/// Applies a linear fade-in to interleaved samples.
+fn fade_in(pcm: &mut [i16], num_channels: usize) {
+    let frames = pcm.len() / num_channels;
+    for (i, frame) in pcm.chunks_exact_mut(num_channels).enumerate() {
+        for sample in frame {
+            *sample = (*sample as i32 * i as i32 / frames as i32) as i16;
+        }
+    }
 }
 
-fn audio_buffer(m4a_info: &M4AInfo, sample_rate: u32) -> Result<AudioBuffer<i16>> {
-    if m4a_info.channels < 1 || m4a_info.channels > 2 {
+/// Copies interleaved samples in FDK's channel order into the buffer.
+fn copy_interleaved(buf: &mut AudioBuffer<i16>, layout: &ChannelLayout, pcm: &[i16]) {
+    if layout.is_canonical() {
+        buf.copy_from_slice_interleaved(&pcm);
+        return;
+    }
+    // FDK outputs channels in bitstream order, so they're moved into canonical order
+    let num_channels = layout.order.len();
+    for (canonical_idx, idx) in layout.order.iter().enumerate() {
+        if let Some(plane) = buf.plane_mut(canonical_idx) {
+            let src = pcm[*idx..].iter().step_by(num_channels);
+            for (dst, src) in plane.iter_mut().zip(src) {
+                *dst = *src;
+            }
+        }
+    }
+}
+
+/// Guesses the stream configuration from the codec parameters for streams that are configured
+/// in-band.
+fn m4a_info_from_params(params: &AudioCodecParameters) -> M4AInfo {
+    let sample_rate = params.sample_rate.unwrap_or_default();
+    M4AInfo {
+        otype: M4AType::Lc,
+        sample_rate,
+        sample_rate_index: sample_rate_index(sample_rate),
+        channels: params
+            .channels
+            .as_ref()
+            .map_or(0, |channels| channels.count() as u8),
+        ..Default::default()
+    }
+}
+
+fn new_decoder(transport: Transport) -> Result<Decoder> {
+    let mut decoder = Decoder::new(transport).map_err(|e| Error::DecodeError(e.message()))?;
+    // FDK mixes anything above 5.1 down to 6 channels by default
+    decoder
+        .set_param(sys::AACDEC_PARAM_AAC_PCM_MAX_OUTPUT_CHANNELS, -1)
+        .map_err(|e| Error::DecodeError(e.message()))?;
+    Ok(decoder)
+}
+
+/// Configures a decoder with the AudioSpecificConfig in the extra data. Returns `None` if the
+/// extra data isn't usable, in which case the config has to come from the packets instead.
+fn raw_decoder(extra_data: &[u8]) -> Result<Option<(Decoder, M4AInfo, Option<AdtsConfig>)>> {
+    let asc = match unwrap_esds(extra_data) {
+        Ok(asc) => asc,
+        Err(e) => {
+            warn!("aac: ignoring invalid esds: {e}");
+            return Ok(None);
+        }
+    };
+    if asc.len() < 2 {
+        warn!("aac: ignoring {} bytes of extra data", asc.len());
+        return Ok(None);
+    }
+    let config = match AudioSpecificConfig::read(asc) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("aac: ignoring invalid audio specific config: {e}");
+            return Ok(None);
+        }
+    };
+
+    // The AudioSpecificConfig describes every configuration FDK supports, unlike ADTS headers,
+    // so the raw packets are decoded directly.
+    let mut decoder = new_decoder(Transport::Raw)?;
+    if let Err(e) = decoder.config_raw(asc) {
+        warn!(
+            "aac: ignoring unsupported audio specific config: {}",
+            e.message()
+        );
+        return Ok(None);
+    }
+    Ok(Some((
+        decoder,
+        M4AInfo::from_config(&config),
+        AdtsConfig::from_asc(&config),
+    )))
+}
+
+/// Returns the profile of the decoded stream, or `None` if there's no profile for its object type.
+/// Implicitly signaled SBR and PS are only known once a frame has been decoded.
+fn codec_profile(stream_info: &sys::CStreamInfo) -> Option<CodecProfile> {
+    let profile = match stream_info.aot {
+        sys::AUDIO_OBJECT_TYPE_AOT_AAC_LC if stream_info.flags & sys::AC_PS_PRESENT != 0 => {
+            CODEC_PROFILE_AAC_HE_V2
+        }
+        sys::AUDIO_OBJECT_TYPE_AOT_AAC_LC if stream_info.flags & sys::AC_SBR_PRESENT != 0 => {
+            CODEC_PROFILE_AAC_HE
+        }
+        sys::AUDIO_OBJECT_TYPE_AOT_AAC_MAIN => CODEC_PROFILE_AAC_MAIN,
+        sys::AUDIO_OBJECT_TYPE_AOT_AAC_LC => CODEC_PROFILE_AAC_LC,
+        sys::AUDIO_OBJECT_TYPE_AOT_AAC_SSR => CODEC_PROFILE_AAC_SSR,
+        sys::AUDIO_OBJECT_TYPE_AOT_AAC_LTP => CODEC_PROFILE_AAC_LTP,
+        sys::AUDIO_OBJECT_TYPE_AOT_SBR => CODEC_PROFILE_AAC_HE,
+        sys::AUDIO_OBJECT_TYPE_AOT_PS => CODEC_PROFILE_AAC_HE_V2,
+        sys::AUDIO_OBJECT_TYPE_AOT_ER_AAC_LD => CODEC_PROFILE_AAC_LD,
+        sys::AUDIO_OBJECT_TYPE_AOT_ER_AAC_ELD => CODEC_PROFILE_AAC_ELD,
+        sys::AUDIO_OBJECT_TYPE_AOT_USAC => CODEC_PROFILE_AAC_USAC,
+        _ => return None,
+    };
+    Some(profile)
+}
+
+fn audio_buffer(
+    layout: &ChannelLayout,
+    sample_rate: u32,
+    samples: usize,
+) -> Result<AudioBuffer<i16>> {
+    let num_channels = layout.channels.count();
+    if !(1..=MAX_CHANNELS).contains(&num_channels) {
         return unsupported_error("aac: unsupported number of channels");
     }
-    let channels = map_to_channels(m4a_info.channels).expect("invalid channels");
     Ok(AudioBuffer::new(
-        AudioSpec::new(sample_rate, channels),
-        m4a_info.samples,
+        AudioSpec::new(sample_rate, layout.channels.clone()),
+        samples,
     ))
 }
 
 impl AudioDecoder for AacDecoder {
-    fn reset(&mut self) {}
+    fn reset(&mut self) {
+        // Drop any bitstream data that was buffered before the seek
+        if let Err(e) = self
+            .decoder
+            .set_param(sys::AACDEC_PARAM_AAC_TPDEC_CLEAR_BUFFER, 1)
+        {
+            warn!("aac: failed to clear transport buffer: {}", e.message());
+        }
+        self.reset_pending = true;
+        self.fade_in = true;
+        self.pending.clear();
+        self.reset_trim();
+        self.buf.clear();
+    }
 
     fn codec_info(&self) -> &CodecInfo {
         &Self::supported_codecs()
@@ -149,48 +761,54 @@ impl AudioDecoder for AacDecoder {
     }
 
     fn decode_ref(&mut self, packet: &PacketRef) -> Result<GenericAudioBufferRef<'_>> {
-        let mut reader = packet.as_buf_reader();
-        let adts_header = construct_adts_header(
-            self.m4a_info.otype,
-            self.m4a_info.sample_rate_index,
-            self.m4a_info.channels,
-            reader.byte_len(),
-        );
-
-        self.decoder
-            .fill(&[&adts_header, reader.read_buf_bytes_available_ref()].concat())
-            .map_err(|e| Error::DecodeError(e.message()))?;
-
-        match self.decoder.decode_frame(&mut self.pcm) {
-            Ok(_) => {}
-            Err(e @ DecoderError::TRANSPORT_SYNC_ERROR) => {
-                warn!("aac: transport sync error: {}", e.message());
-                self.buf.clear();
-                return Ok(self.buf.as_generic_audio_buffer_ref());
+        self.samples.clear();
+        self.config_changed = false;
+        if self.detect_transport {
+            self.detect_transport(packet.data)?;
+        }
+        let pending = PendingPacket::new(packet);
+        if let Some(frames) = self.adts_frames(packet.data)? {
+            // Some demuxers leave the ADTS headers in the packets, sometimes with several frames
+            // in each one
+            for (frame, pending) in frames.iter().zip(pending.split(frames.len())) {
+                let data = match self.transport {
+                    Transport::Raw => frame.payload()?,
+                    _ => frame.data,
+                };
+                self.decode_frame(data, pending)?;
             }
-            Err(e) => {
-                return Err(Error::DecodeError(e.message()));
+        } else if self.transport == Transport::Adts {
+            if self.m4a_info.channels == 0 || self.m4a_info.sample_rate == 0 {
+                return unsupported_error(
+                    "aac: packets need adts headers without a config or codec parameters",
+                );
             }
-        }
-        if !self.m4a_info_validated {
-            self.configure_metadata()?;
+            let adts_header = construct_adts_header(
+                self.m4a_info.otype,
+                self.m4a_info.sample_rate_index,
+                self.m4a_info.channels,
+                packet.data.len() as u64,
+            );
+            self.decode_frame(&[&adts_header, packet.data].concat(), pending)?;
+        } else {
+            self.decode_frame(packet.data, pending)?;
         }
 
-        let capacity = self.decoder.decoded_frame_size();
-        let pcm = &self.pcm[..capacity];
+        let frames = self.samples.len() / self.layout.order.len();
         self.buf.clear();
-
-        self.buf.render_uninit(None);
-        self.buf.copy_from_slice_interleaved(&pcm);
-        self.buf.trim(
-            packet.trim_start.get() as usize,
-            packet.trim_end.get() as usize,
-        );
+        self.buf.grow_capacity(frames);
+        self.buf.render_uninit(Some(frames));
+        copy_interleaved(&mut self.buf, &self.layout, &self.samples);
 
         Ok(self.buf.as_generic_audio_buffer_ref())
     }
 
     fn finalize(&mut self) -> FinalizeResult {
+        // The drained audio is left in the buffer for last_decoded
+        if let Err(e) = self.drain() {
+            warn!("aac: failed to drain decoder: {e}");
+            self.buf.clear();
+        }
         FinalizeResult::default()
     }
 
@@ -211,15 +829,25 @@ impl RegisterableAudioDecoder for AacDecoder {
     }
 
     fn supported_codecs() -> &'static [SupportedAudioCodec] {
-        &[support_audio_codec!(
-            CODEC_ID_AAC,
-            "aac",
-            "Advanced Audio Coding",
-            &[
-                codec_profile!(CODEC_PROFILE_AAC_LC, "aac-lc", "Low Complexity"),
-                codec_profile!(CODEC_PROFILE_AAC_HE, "aac-he", "High Efficiency"),
-                codec_profile!(CODEC_PROFILE_AAC_HE_V2, "aac-he-v2", "High Efficiency V2"),
-            ]
-        )]
+        &[
+            support_audio_codec!(
+                CODEC_ID_AAC,
+                "aac",
+                "Advanced Audio Coding",
+                &[
+                    codec_profile!(CODEC_PROFILE_AAC_LC, "aac-lc", "Low Complexity"),
+                    codec_profile!(CODEC_PROFILE_AAC_HE, "aac-he", "High Efficiency"),
+                    codec_profile!(CODEC_PROFILE_AAC_HE_V2, "aac-he-v2", "High Efficiency V2"),
+                    codec_profile!(CODEC_PROFILE_AAC_LD, "aac-ld", "Low Delay"),
+                    codec_profile!(CODEC_PROFILE_AAC_ELD, "aac-eld", "Enhanced Low Delay"),
+                    codec_profile!(CODEC_PROFILE_AAC_USAC, "aac-usac", "USAC (xHE-AAC)"),
+                ]
+            ),
+            support_audio_codec!(
+                CODEC_ID_AAC_LATM,
+                "aac-latm",
+                "Advanced Audio Coding (LATM)"
+            ),
+        ]
     }
 }
@@ -0,0 +1,325 @@
+use std::io::{Seek, SeekFrom};
+
+use log::{debug, warn};
+use symphonia_core::codecs::CodecParameters;
+use symphonia_core::codecs::audio::AudioCodecParameters;
+use symphonia_core::common::FourCc;
+use symphonia_core::errors::{Error, Result, SeekErrorKind, decode_error, seek_error};
+use symphonia_core::formats::prelude::*;
+use symphonia_core::formats::probe::{ProbeFormatData, ProbeableFormat, Score, Scoreable};
+use symphonia_core::io::{
+    BitReaderLtr, MediaSource, MediaSourceStream, ReadBitsLtr, ReadBytes, ScopedStream,
+    SeekBuffered,
+};
+use symphonia_core::meta::{Metadata, MetadataLog};
+use symphonia_core::support_format;
+
+use crate::CODEC_ID_AAC_LATM;
+use crate::meta::{M4AInfo, map_to_channels};
+
+/// LOAS (Low Overhead Audio Stream) format ID.
+pub const FORMAT_ID_LOAS: FormatId = FormatId::new(FourCc::new(*b"loas"));
+
+const LOAS_FORMAT_INFO: FormatInfo = FormatInfo {
+    format: FORMAT_ID_LOAS,
+    short_name: "loas",
+    long_name: "Low Overhead Audio Stream",
+};
+
+/// Every frame starts with this 11 bit sync word.
+const SYNC_WORD: u32 = 0x2b7;
+const HEADER_LEN: usize = 3;
+/// Frame lengths are stored in 13 bits.
+const MAX_FRAME_LEN: usize = HEADER_LEN + 0x1fff;
+/// Number of frames to search for a StreamMuxConfig before giving up.
+const MAX_CONFIG_SEARCH_FRAMES: usize = 64;
+
+/// The last 5 bits of the second byte belong to the frame length, so the sync word can be
+/// followed by any of them.
+const MARKERS: &[&[u8]] = &[
+    &[0x56, 0xe0],
+    &[0x56, 0xe1],
+    &[0x56, 0xe2],
+    &[0x56, 0xe3],
+    &[0x56, 0xe4],
+    &[0x56, 0xe5],
+    &[0x56, 0xe6],
+    &[0x56, 0xe7],
+    &[0x56, 0xe8],
+    &[0x56, 0xe9],
+    &[0x56, 0xea],
+    &[0x56, 0xeb],
+    &[0x56, 0xec],
+    &[0x56, 0xed],
+    &[0x56, 0xee],
+    &[0x56, 0xef],
+    &[0x56, 0xf0],
+    &[0x56, 0xf1],
+    &[0x56, 0xf2],
+    &[0x56, 0xf3],
+    &[0x56, 0xf4],
+    &[0x56, 0xf5],
+    &[0x56, 0xf6],
+    &[0x56, 0xf7],
+    &[0x56, 0xf8],
+    &[0x56, 0xf9],
+    &[0x56, 0xfa],
+    &[0x56, 0xfb],
+    &[0x56, 0xfc],
+    &[0x56, 0xfd],
+    &[0x56, 0xfe],
+    &[0x56, 0xff],
+];
+
+/// Reader for AAC in a LOAS AudioSyncStream, as found in DVB and ISDB broadcasts.
+///
+/// The codec parameters are taken from the first StreamMuxConfig in the stream. Packets are
+/// whole LOAS frames with the [`CODEC_ID_AAC_LATM`] codec, which
+/// [`AacDecoder`](crate::AacDecoder) decodes using the StreamMuxConfigs in the stream.
+pub struct LoasReader<'s> {
+    reader: MediaSourceStream<'s>,
+    tracks: Vec<Track>,
+    media_info: MediaInfo,
+    metadata: MetadataLog,
+    first_frame_offset: u64,
+    /// Duration of each frame in samples per channel, including all of its subframes.
+    frame_dur: u64,
+    next_ts: u64,
+}
+
+impl std::fmt::Debug for LoasReader<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoasReader")
+            .field("tracks", &self.tracks)
+            .field("media_info", &self.media_info)
+            .field("first_frame_offset", &self.first_frame_offset)
+            .field("frame_dur", &self.frame_dur)
+            .field("next_ts", &self.next_ts)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'s> LoasReader<'s> {
+    /// Creates a reader, starting from the first frame that contains a StreamMuxConfig.
+    pub fn try_new(mut mss: MediaSourceStream<'s>, opts: FormatOptions) -> Result<Self> {
+        // The frame with the config is read twice, once here and once as a packet
+        mss.ensure_seekback_buffer(MAX_FRAME_LEN);
+
+        let mut info = M4AInfo::default();
+        let mut payloads = None;
+        for _ in 0..MAX_CONFIG_SEARCH_FRAMES {
+            let len = sync(&mut mss)?;
+            let frame = mss.read_boxed_slice_exact(len)?;
+            let mut bs = BitReaderLtr::new(&frame);
+            // Frames that reuse the previous config can't be decoded until a config is found
+            let use_same_stream_mux = bs.read_bool()?;
+            if !use_same_stream_mux {
+                payloads = Some(info.read_stream_mux_config(&mut bs)?);
+                mss.seek_buffered_rel(-((HEADER_LEN + len) as isize));
+                break;
+            }
+            debug!("loas: skipping frame without a stream mux config");
+        }
+        let Some(payloads) = payloads else {
+            return decode_error("loas: missing stream mux config");
+        };
+
+        let sample_rate = info.sample_rate;
+        // Broadcasters often group several access units into each frame, which are all decoded
+        // from the same packet
+        let frame_dur = info.samples as u64 * u64::from(payloads);
+        let mut codec_params = AudioCodecParameters::new();
+        codec_params
+            .for_codec(CODEC_ID_AAC_LATM)
+            .with_sample_rate(sample_rate)
+            .with_max_frames_per_packet(frame_dur);
+        match info.layout.or_else(|| map_to_channels(info.channels)) {
+            Some(channels) => {
+                codec_params.with_channels(channels);
+            }
+            None => warn!("loas: unsupported channel configuration {}", info.channels),
+        }
+
+        let Some(time_base) = TimeBase::try_new(1, sample_rate) else {
+            return decode_error("loas: invalid sample rate");
+        };
+        let mut track = Track::new(0);
+        track
+            .with_codec_params(CodecParameters::Audio(codec_params))
+            .with_time_base(time_base);
+
+        Ok(Self {
+            first_frame_offset: mss.pos(),
+            reader: mss,
+            media_info: MediaInfo::from_track(&track),
+            tracks: vec![track],
+            metadata: opts.external_data.metadata.unwrap_or_default(),
+            frame_dur,
+            next_ts: 0,
+        })
+    }
+}
+
+/// Checks if the data starts with a LOAS sync word.
+pub(crate) fn has_loas_header(data: &[u8]) -> bool {
+    data.len() >= HEADER_LEN && u32::from(u16::from_be_bytes([data[0], data[1]]) >> 5) == SYNC_WORD
+}
+
+/// Finds the next frame header and returns the length of the AudioMuxElement that follows it.
+fn sync<B: ReadBytes>(reader: &mut B) -> Result<usize> {
+    let mut header = reader.read_be_u24()?;
+    while header >> 13 != SYNC_WORD {
+        header = (header << 8 | u32::from(reader.read_u8()?)) & 0xff_ffff;
+    }
+    Ok((header & 0x1fff) as usize)
+}
+
+impl Scoreable for LoasReader<'_> {
+    fn score(mut src: ScopedStream<&mut MediaSourceStream<'_>>) -> Result<Score> {
+        // The sync word is short enough to show up by chance, so check that the next frame
+        // follows directly after the first one.
+        let header = src.read_be_u24()?;
+        if header >> 13 != SYNC_WORD {
+            return Ok(Score::Unsupported);
+        }
+        src.ignore_bytes(u64::from(header & 0x1fff))?;
+        if u32::from(src.read_be_u16()? >> 5) == SYNC_WORD {
+            Ok(Score::Supported(255))
+        } else {
+            Ok(Score::Unsupported)
+        }
+    }
+}
+
+impl ProbeableFormat<'_> for LoasReader<'_> {
+    fn try_probe_new(
+        mss: MediaSourceStream<'_>,
+        opts: FormatOptions,
+    ) -> Result<Box<dyn FormatReader + '_>> {
+        Ok(Box::new(LoasReader::try_new(mss, opts)?))
+    }
+
+    fn probe_data() -> &'static [ProbeFormatData] {
+        &[support_format!(
+            LOAS_FORMAT_INFO,
+            &["loas", "latm"],
+            &["audio/mp4a-latm"],
+            MARKERS
+        )]
+    }
+}
+
+impl FormatReader for LoasReader<'_> {
+    fn format_info(&self) -> &FormatInfo {
+        &LOAS_FORMAT_INFO
+    }
+
+    fn media_info(&self) -> &MediaInfo {
+        &self.media_info
+    }
+
+    fn metadata(&mut self) -> Metadata<'_> {
+        self.metadata.metadata()
+    }
+
+    fn seek(&mut self, _mode: SeekMode, to: SeekTo) -> Result<SeekedTo> {
+        let ts = match to {
+            SeekTo::Timestamp { ts, .. } => ts,
+            SeekTo::Time { time, .. } => {
+                let time_base = self.media_info.time_base;
+                time_base
+                    .and_then(|time_base| time_base.calc_timestamp(time))
+                    .ok_or(Error::SeekError(SeekErrorKind::OutOfRange))?
+            }
+        };
+        let Ok(required_ts) = u64::try_from(ts.get()) else {
+            return seek_error(SeekErrorKind::OutOfRange);
+        };
+
+        // Frames don't contain timestamps, so they're counted from the start of the stream
+        let seekable = self.reader.is_seekable();
+        if seekable {
+            self.reader.seek(SeekFrom::Start(self.first_frame_offset))?;
+            self.next_ts = 0;
+        } else if required_ts < self.next_ts {
+            return seek_error(SeekErrorKind::ForwardOnly);
+        }
+        // The decoder loses the config when it's reset, so decoding has to restart from the last
+        // frame that contains a StreamMuxConfig.
+        let mut config_frame = None;
+        loop {
+            let pos = self.reader.pos();
+            let len = match sync(&mut self.reader) {
+                Ok(len) if len > 0 => len,
+                Ok(_) => return decode_error("loas: empty frame"),
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return seek_error(SeekErrorKind::OutOfRange);
+                }
+                Err(e) => return Err(e),
+            };
+            let use_same_stream_mux = self.reader.read_u8()? & 0x80 != 0;
+            if !use_same_stream_mux {
+                config_frame = Some((pos, self.next_ts));
+            }
+            if self.next_ts + self.frame_dur > required_ts {
+                match config_frame {
+                    Some((pos, ts)) if seekable => {
+                        self.reader.seek(SeekFrom::Start(pos))?;
+                        self.next_ts = ts;
+                    }
+                    _ => {
+                        self.reader.seek_buffered_rel(-((HEADER_LEN + 1) as isize));
+                    }
+                }
+                break;
+            }
+            self.reader.ignore_bytes(len as u64 - 1)?;
+            self.next_ts += self.frame_dur;
+        }
+        debug!(
+            "loas: seeked to ts={} (required ts={required_ts})",
+            self.next_ts
+        );
+
+        Ok(SeekedTo {
+            track_id: 0,
+            required_ts: ts,
+            actual_ts: Timestamp::new(self.next_ts as i64),
+        })
+    }
+
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn next_packet(&mut self) -> Result<Option<Packet>> {
+        let len = match sync(&mut self.reader) {
+            Ok(len) => len,
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        let mut data = vec![0; HEADER_LEN + len];
+        data[0] = (SYNC_WORD >> 3) as u8;
+        data[1] = ((SYNC_WORD << 5) as u8) | (len >> 8) as u8;
+        data[2] = len as u8;
+        self.reader.read_buf_exact(&mut data[HEADER_LEN..])?;
+
+        let packet = Packet::new(
+            0,
+            Timestamp::new(self.next_ts as i64),
+            Duration::new(self.frame_dur),
+            data,
+        );
+        self.next_ts += self.frame_dur;
+        Ok(Some(packet))
+    }
+
+    fn into_inner<'s>(self: Box<Self>) -> MediaSourceStream<'s>
+    where
+        Self: 's,
+    {
+        self.reader
+    }
+}
@@ -0,0 +1,810 @@
+use symphonia_core::audio::{Channels, Position, layouts};
+use symphonia_core::errors::{Result, unsupported_error};
+use symphonia_core::io::{BitReaderLtr, FiniteBitStream, ReadBitsLtr};
+
+use super::bits::{BitWriter, RawConfig};
+use super::pce::ProgramConfig;
+use super::{
+    AAC_CHANNELS, AAC_SAMPLE_RATES, M4A_TYPES, M4AType, USAC_FRAME_LENGTHS, USAC_SAMPLE_RATES,
+    map_to_channels,
+};
+use crate::macros::validate;
+
+/// Sync word of the backward compatible SBR signaling after the object type specific config.
+const SBR_SYNC_WORD: u32 = 0x2b7;
+/// Sync word of the backward compatible PS signaling after the SBR signaling.
+const PS_SYNC_WORD: u32 = 0x548;
+const ESCAPE_SAMPLE_RATE_INDEX: u32 = 0xf;
+const ESCAPE_OBJECT_TYPE: u32 = 31;
+
+/// MPEG-4 AudioSpecificConfig (ISO/IEC 14496-3, 1.6.2.1), as found in the extra data of AAC
+/// tracks.
+///
+/// Sample rates from the standard table and object types are always written in their shortest
+/// form, so configs that use escape values for them won't round trip exactly.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AudioSpecificConfig {
+    /// Object type of the core codec. When SBR or PS is signaled hierarchically, this is the type
+    /// of the underlying stream.
+    pub object_type: M4AType,
+    /// Sample rate of the core codec.
+    pub sample_rate: u32,
+    /// Channel configuration index. With configuration 0, the channels are defined by the program
+    /// config element or the USAC config.
+    pub channel_config: u8,
+    /// Explicitly signaled SBR and PS. Streams without it may still use implicit SBR, which
+    /// isn't known until the first frame is decoded.
+    pub sbr: Option<SbrConfig>,
+    /// Config specific to the object type.
+    pub specific_config: SpecificConfig,
+    /// Error protection config of the error resilient object types.
+    pub ep_config: Option<u8>,
+}
+
+/// How SBR is signaled in the AudioSpecificConfig.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SbrSignaling {
+    /// The SBR or PS object type comes first, followed by the core object type. Decoders without
+    /// SBR support can't decode these streams.
+    Hierarchical,
+    /// The SBR extension follows the core config after a sync word, so decoders without SBR
+    /// support can ignore it.
+    BackwardCompatible,
+}
+
+/// Explicitly signaled spectral band replication (SBR) and parametric stereo (PS).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SbrConfig {
+    /// How SBR is signaled.
+    pub signaling: SbrSignaling,
+    /// Whether SBR is used. Backward compatible signaling can also be used to rule out SBR.
+    pub present: bool,
+    /// Output sample rate of SBR. This is the core sample rate if SBR isn't present.
+    pub sample_rate: u32,
+    /// Whether PS is used, if it's signaled.
+    pub ps: Option<bool>,
+}
+
+/// Config specific to the object type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpecificConfig {
+    /// GASpecificConfig, used by AAC Main, LC, SSR, LTP, scalable, TwinVQ, and their error
+    /// resilient versions, including AAC LD.
+    Ga(GaSpecificConfig),
+    /// ELDSpecificConfig, used by AAC ELD.
+    Eld(EldSpecificConfig),
+    /// UsacConfig, used by USAC.
+    Usac(UsacConfig),
+    /// Config of any other object type, which isn't parsed. This is the rest of the
+    /// AudioSpecificConfig.
+    Other(RawConfig),
+}
+
+/// Error resilience tools used by the error resilient object types.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ErrorResilience {
+    /// Virtual codebooks are used for the section data.
+    pub section_data: bool,
+    /// Reversible variable length coding is used for the scalefactor data.
+    pub scalefactor_data: bool,
+    /// Huffman codeword reordering is used for the spectral data.
+    pub spectral_data: bool,
+}
+
+impl ErrorResilience {
+    fn read<B: ReadBitsLtr>(bs: &mut B) -> Result<Self> {
+        Ok(Self {
+            section_data: bs.read_bool()?,
+            scalefactor_data: bs.read_bool()?,
+            spectral_data: bs.read_bool()?,
+        })
+    }
+
+    fn write(&self, bw: &mut BitWriter) {
+        bw.write_bool(self.section_data);
+        bw.write_bool(self.scalefactor_data);
+        bw.write_bool(self.spectral_data);
+    }
+}
+
+/// GASpecificConfig (ISO/IEC 14496-3, 4.4.1).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GaSpecificConfig {
+    /// Frames are 960 samples long instead of 1024, or 480 instead of 512 for AAC LD.
+    pub frame_length_flag: bool,
+    /// Delay of the core coder in samples, for scalable streams.
+    pub core_coder_delay: Option<u16>,
+    /// Program config element, for channel configuration 0.
+    pub program_config: Option<ProgramConfig>,
+    /// Layer number, for scalable streams.
+    pub layer: Option<u8>,
+    /// The config is extended with the fields below.
+    pub extension_flag: bool,
+    /// Error resilience tools, for the error resilient AAC object types.
+    pub resilience: Option<ErrorResilience>,
+    /// Reserved for future versions of the config.
+    pub extension_flag_3: bool,
+}
+
+impl GaSpecificConfig {
+    fn read<B: ReadBitsLtr + FiniteBitStream>(
+        bs: &mut B,
+        object_type: M4AType,
+        channel_config: u8,
+        start: u64,
+    ) -> Result<Self> {
+        let mut config = Self {
+            frame_length_flag: bs.read_bool()?,
+            ..Default::default()
+        };
+        if bs.read_bool()? {
+            config.core_coder_delay = Some(bs.read_bits_leq32(14)? as u16);
+        }
+        config.extension_flag = bs.read_bool()?;
+        if channel_config == 0 {
+            config.program_config = Some(ProgramConfig::read(bs, start)?);
+        }
+        if is_scalable(object_type) {
+            config.layer = Some(bs.read_bits_leq32(3)? as u8);
+        }
+        if config.extension_flag {
+            if has_resilience_tools(object_type) {
+                config.resilience = Some(ErrorResilience::read(bs)?);
+            }
+            config.extension_flag_3 = bs.read_bool()?;
+        }
+        Ok(config)
+    }
+
+    fn write(&self, bw: &mut BitWriter, object_type: M4AType) {
+        bw.write_bool(self.frame_length_flag);
+        bw.write_bool(self.core_coder_delay.is_some());
+        if let Some(delay) = self.core_coder_delay {
+            bw.write_bits(u32::from(delay), 14);
+        }
+        bw.write_bool(self.extension_flag);
+        if let Some(pce) = &self.program_config {
+            pce.write(bw);
+        }
+        if is_scalable(object_type) {
+            bw.write_bits(u32::from(self.layer.unwrap_or_default()), 3);
+        }
+        if self.extension_flag {
+            if has_resilience_tools(object_type) {
+                self.resilience.unwrap_or_default().write(bw);
+            }
+            bw.write_bool(self.extension_flag_3);
+        }
+    }
+}
+
+/// ELDSpecificConfig (ISO/IEC 14496-3, 4.4.1.2).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EldSpecificConfig {
+    /// Frames are 480 samples long instead of 512.
+    pub frame_length_flag: bool,
+    /// Error resilience tools.
+    pub resilience: ErrorResilience,
+    /// Low delay SBR config, if it's used.
+    pub ld_sbr: Option<LdSbrConfig>,
+    /// Extension type and payload of each ELD extension.
+    extensions: Vec<(u8, Vec<u8>)>,
+}
+
+/// Low delay SBR config of an ELD stream.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LdSbrConfig {
+    /// SBR runs at twice the core sample rate, rather than downsampling the output to it.
+    pub dual_rate: bool,
+    /// SBR data is protected by a CRC.
+    pub crc: bool,
+    headers: Vec<SbrHeader>,
+}
+
+/// SBR header of a low delay SBR config, which is only kept so that it can be written back.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct SbrHeader {
+    /// The amplitude resolution, frequency band and reserved fields.
+    fields: u16,
+    extra_1: Option<u8>,
+    extra_2: Option<u8>,
+}
+
+impl SbrHeader {
+    fn read<B: ReadBitsLtr>(bs: &mut B) -> Result<Self> {
+        let fields = bs.read_bits_leq32(14)? as u16;
+        let has_extra_1 = bs.read_bool()?;
+        let has_extra_2 = bs.read_bool()?;
+        Ok(Self {
+            fields,
+            extra_1: has_extra_1
+                .then(|| bs.read_bits_leq32(5))
+                .transpose()?
+                .map(|v| v as u8),
+            extra_2: has_extra_2
+                .then(|| bs.read_bits_leq32(6))
+                .transpose()?
+                .map(|v| v as u8),
+        })
+    }
+
+    fn write(&self, bw: &mut BitWriter) {
+        bw.write_bits(u32::from(self.fields), 14);
+        bw.write_bool(self.extra_1.is_some());
+        bw.write_bool(self.extra_2.is_some());
+        if let Some(extra) = self.extra_1 {
+            bw.write_bits(u32::from(extra), 5);
+        }
+        if let Some(extra) = self.extra_2 {
+            bw.write_bits(u32::from(extra), 6);
+        }
+    }
+}
+
+impl EldSpecificConfig {
+    fn read<B: ReadBitsLtr>(bs: &mut B, channel_config: u8) -> Result<Self> {
+        let mut config = Self {
+            frame_length_flag: bs.read_bool()?,
+            resilience: ErrorResilience::read(bs)?,
+            ..Default::default()
+        };
+        if bs.read_bool()? {
+            let mut ld_sbr = LdSbrConfig {
+                dual_rate: bs.read_bool()?,
+                crc: bs.read_bool()?,
+                headers: Vec::new(),
+            };
+            for _ in 0..num_sbr_headers(channel_config) {
+                ld_sbr.headers.push(SbrHeader::read(bs)?);
+            }
+            config.ld_sbr = Some(ld_sbr);
+        }
+
+        loop {
+            let ext_type = bs.read_bits_leq32(4)? as u8;
+            // ELDEXT_TERM
+            if ext_type == 0 {
+                break;
+            }
+            let mut len = bs.read_bits_leq32(4)?;
+            if len == 0xf {
+                let len_add = bs.read_bits_leq32(8)?;
+                len += len_add;
+                if len_add == 0xff {
+                    len += bs.read_bits_leq32(16)?;
+                }
+            }
+            let payload = (0..len)
+                .map(|_| Ok(bs.read_bits_leq32(8)? as u8))
+                .collect::<Result<_>>()?;
+            config.extensions.push((ext_type, payload));
+        }
+        Ok(config)
+    }
+
+    fn write(&self, bw: &mut BitWriter) {
+        bw.write_bool(self.frame_length_flag);
+        self.resilience.write(bw);
+        bw.write_bool(self.ld_sbr.is_some());
+        if let Some(ld_sbr) = &self.ld_sbr {
+            bw.write_bool(ld_sbr.dual_rate);
+            bw.write_bool(ld_sbr.crc);
+            for header in &ld_sbr.headers {
+                header.write(bw);
+            }
+        }
+
+        for (ext_type, payload) in &self.extensions {
+            bw.write_bits(u32::from(*ext_type), 4);
+            let len = payload.len() as u32;
+            if len < 0xf {
+                bw.write_bits(len, 4);
+            } else if len < 0xf + 0xff {
+                bw.write_bits(0xf, 4);
+                bw.write_bits(len - 0xf, 8);
+            } else {
+                bw.write_bits(0xf, 4);
+                bw.write_bits(0xff, 8);
+                bw.write_bits(len - 0xf - 0xff, 16);
+            }
+            for byte in payload {
+                bw.write_bits(u32::from(*byte), 8);
+            }
+        }
+        bw.write_bits(0, 4);
+    }
+}
+
+/// Number of SBR headers in a low delay SBR config, one per channel element.
+fn num_sbr_headers(channel_config: u8) -> usize {
+    match channel_config {
+        1 | 2 => 1,
+        3 => 2,
+        4..=6 => 3,
+        7 => 4,
+        _ => 0,
+    }
+}
+
+/// UsacConfig (ISO/IEC 23003-3, 5.2). Only the fields that describe the output are parsed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UsacConfig {
+    /// Output sample rate.
+    pub sample_rate: u32,
+    /// Index of the core coder and SBR frame lengths.
+    pub core_sbr_frame_length_index: u8,
+    /// Channel configuration index, which replaces the one in the AudioSpecificConfig.
+    pub channel_config: u8,
+    /// Speaker position index of each output channel, for channel configuration 0.
+    pub output_channel_positions: Vec<u8>,
+    /// UsacDecoderConfig and any config extensions.
+    decoder_config: RawConfig,
+}
+
+impl UsacConfig {
+    fn read<B: ReadBitsLtr + FiniteBitStream>(bs: &mut B, end: u64) -> Result<Self> {
+        let sample_rate = match bs.read_bits_leq32(5)? {
+            0x1f => bs.read_bits_leq32(24)?,
+            idx => USAC_SAMPLE_RATES[idx as usize],
+        };
+        validate!(sample_rate > 0);
+
+        let core_sbr_frame_length_index = bs.read_bits_leq32(3)? as u8;
+        validate!((core_sbr_frame_length_index as usize) < USAC_FRAME_LENGTHS.len());
+
+        let channel_config = bs.read_bits_leq32(5)? as u8;
+        let mut output_channel_positions = Vec::new();
+        if channel_config == 0 {
+            let num_channels = read_escaped_value(bs, 5, 8, 16)?;
+            for _ in 0..num_channels {
+                output_channel_positions.push(bs.read_bits_leq32(5)? as u8);
+            }
+        }
+
+        let decoder_config = RawConfig::read(bs, bs.bits_left().saturating_sub(end))?;
+        Ok(Self {
+            sample_rate,
+            core_sbr_frame_length_index,
+            channel_config,
+            output_channel_positions,
+            decoder_config,
+        })
+    }
+
+    fn write(&self, bw: &mut BitWriter) {
+        match USAC_SAMPLE_RATES
+            .iter()
+            .position(|s| *s == self.sample_rate)
+        {
+            Some(idx) if self.sample_rate > 0 => bw.write_bits(idx as u32, 5),
+            _ => {
+                bw.write_bits(0x1f, 5);
+                bw.write_bits(self.sample_rate, 24);
+            }
+        }
+        bw.write_bits(u32::from(self.core_sbr_frame_length_index), 3);
+        bw.write_bits(u32::from(self.channel_config), 5);
+        if self.channel_config == 0 {
+            write_escaped_value(bw, self.output_channel_positions.len() as u32, 5, 8, 16);
+            for pos in &self.output_channel_positions {
+                bw.write_bits(u32::from(*pos), 5);
+            }
+        }
+        self.decoder_config.write(bw);
+    }
+
+    /// Channel layout of the output, or `None` if the channel configuration is unknown.
+    fn channels(&self) -> Option<Channels> {
+        if self.channel_config != 0 {
+            return config_channels(self.channel_config);
+        }
+        let positions = self
+            .output_channel_positions
+            .iter()
+            .map(|pos| USAC_POSITIONS.get(*pos as usize).copied().flatten())
+            .collect::<Option<Vec<_>>>();
+        let mask = positions
+            .iter()
+            .flatten()
+            .fold(Position::empty(), |acc, pos| acc.union(*pos));
+        match positions {
+            Some(positions)
+                if !positions.is_empty()
+                    && mask.bits().count_ones() as usize == positions.len() =>
+            {
+                Some(Channels::Positioned(mask))
+            }
+            _ => Some(Channels::Discrete(
+                self.output_channel_positions.len() as u16
+            )),
+        }
+    }
+}
+
+/// Speaker positions for each USAC output channel position index, where Symphonia has one.
+const USAC_POSITIONS: [Option<Position>; 32] = [
+    Some(Position::FRONT_LEFT),
+    Some(Position::FRONT_RIGHT),
+    Some(Position::FRONT_CENTER),
+    Some(Position::LFE1),
+    Some(Position::SIDE_LEFT),
+    Some(Position::SIDE_RIGHT),
+    Some(Position::FRONT_LEFT_CENTER),
+    Some(Position::FRONT_RIGHT_CENTER),
+    Some(Position::REAR_LEFT),
+    Some(Position::REAR_RIGHT),
+    Some(Position::REAR_CENTER),
+    None,
+    None,
+    None,
+    None,
+    Some(Position::FRONT_LEFT_WIDE),
+    Some(Position::FRONT_RIGHT_WIDE),
+    Some(Position::TOP_FRONT_LEFT),
+    Some(Position::TOP_FRONT_RIGHT),
+    Some(Position::TOP_FRONT_CENTER),
+    Some(Position::TOP_REAR_LEFT),
+    Some(Position::TOP_REAR_RIGHT),
+    Some(Position::TOP_REAR_CENTER),
+    Some(Position::TOP_SIDE_LEFT),
+    Some(Position::TOP_SIDE_RIGHT),
+    Some(Position::TOP_CENTER),
+    Some(Position::LFE2),
+    Some(Position::BOTTOM_FRONT_LEFT),
+    Some(Position::BOTTOM_FRONT_RIGHT),
+    Some(Position::BOTTOM_FRONT_CENTER),
+    None,
+    None,
+];
+
+impl AudioSpecificConfig {
+    /// Parses an AudioSpecificConfig.
+    pub fn read(buf: &[u8]) -> Result<Self> {
+        Self::read_bits(&mut BitReaderLtr::new(buf), 0)
+    }
+
+    /// Reads a config that ends when `end` bits are left in the reader. The end is only used for
+    /// the parts of the config that run until the end, so it may be too early if the length of
+    /// the config isn't known.
+    pub(crate) fn read_bits<B: ReadBitsLtr + FiniteBitStream>(
+        bs: &mut B,
+        end: u64,
+    ) -> Result<Self> {
+        let start = bs.bits_left();
+        let mut object_type = read_object_type(bs)?;
+        let sample_rate = read_sample_rate(bs)?;
+        validate!(sample_rate > 0);
+        let channel_config = bs.read_bits_leq32(4)? as u8;
+
+        let mut sbr = None;
+        if matches!(object_type, M4AType::Sbr | M4AType::PS) {
+            sbr = Some(SbrConfig {
+                signaling: SbrSignaling::Hierarchical,
+                present: true,
+                sample_rate: read_sample_rate(bs)?,
+                ps: (object_type == M4AType::PS).then_some(true),
+            });
+            object_type = read_object_type(bs)?;
+            // BSAC signals its own extension channels here
+            if object_type == M4AType::ER_BSAC {
+                return unsupported_error("aac: bsac with sbr is not supported");
+            }
+        }
+
+        let specific_config = match object_type {
+            M4AType::ER_AAC_ELD => {
+                SpecificConfig::Eld(EldSpecificConfig::read(bs, channel_config)?)
+            }
+            M4AType::Usac => SpecificConfig::Usac(UsacConfig::read(bs, end)?),
+            ty if is_general_audio(ty) => SpecificConfig::Ga(GaSpecificConfig::read(
+                bs,
+                object_type,
+                channel_config,
+                start,
+            )?),
+            _ => SpecificConfig::Other(RawConfig::read(bs, bs.bits_left().saturating_sub(end))?),
+        };
+        let parsed = matches!(
+            specific_config,
+            SpecificConfig::Ga(_) | SpecificConfig::Eld(_)
+        );
+
+        let mut ep_config = None;
+        if parsed && is_error_resilient(object_type) {
+            let ep = bs.read_bits_leq32(2)? as u8;
+            // Configs 2 and 3 are followed by an ErrorProtectionSpecificConfig
+            if ep > 1 {
+                return unsupported_error("aac: unsupported error protection config");
+            }
+            ep_config = Some(ep);
+        }
+
+        if parsed && sbr.is_none() && bs.bits_left() >= end + 16 {
+            sbr = read_sync_extension(bs, sample_rate, end)?;
+        }
+
+        Ok(Self {
+            object_type,
+            sample_rate,
+            channel_config,
+            sbr,
+            specific_config,
+            ep_config,
+        })
+    }
+
+    /// Serializes the config, padded to a whole number of bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bw = BitWriter::default();
+        let hierarchical = self
+            .sbr
+            .filter(|sbr| sbr.signaling == SbrSignaling::Hierarchical);
+        match hierarchical {
+            Some(sbr) if sbr.ps == Some(true) => write_object_type(&mut bw, M4AType::PS),
+            Some(_) => write_object_type(&mut bw, M4AType::Sbr),
+            None => write_object_type(&mut bw, self.object_type),
+        }
+        write_sample_rate(&mut bw, self.sample_rate);
+        bw.write_bits(u32::from(self.channel_config), 4);
+        if let Some(sbr) = hierarchical {
+            write_sample_rate(&mut bw, sbr.sample_rate);
+            write_object_type(&mut bw, self.object_type);
+        }
+
+        match &self.specific_config {
+            SpecificConfig::Ga(config) => config.write(&mut bw, self.object_type),
+            SpecificConfig::Eld(config) => config.write(&mut bw),
+            SpecificConfig::Usac(config) => config.write(&mut bw),
+            SpecificConfig::Other(config) => config.write(&mut bw),
+        }
+        if let Some(ep_config) = self.ep_config {
+            bw.write_bits(u32::from(ep_config), 2);
+        }
+
+        if let Some(sbr) = self
+            .sbr
+            .filter(|sbr| sbr.signaling == SbrSignaling::BackwardCompatible)
+        {
+            bw.write_bits(SBR_SYNC_WORD, 11);
+            write_object_type(&mut bw, M4AType::Sbr);
+            bw.write_bool(sbr.present);
+            if sbr.present {
+                write_sample_rate(&mut bw, sbr.sample_rate);
+                if let Some(ps) = sbr.ps {
+                    bw.write_bits(PS_SYNC_WORD, 11);
+                    bw.write_bool(ps);
+                }
+            }
+        }
+        bw.into_bytes()
+    }
+
+    /// Sample rate of the decoded audio. Implicit SBR isn't taken into account.
+    pub fn output_sample_rate(&self) -> u32 {
+        match &self.specific_config {
+            SpecificConfig::Usac(config) => config.sample_rate,
+            SpecificConfig::Eld(EldSpecificConfig {
+                ld_sbr:
+                    Some(LdSbrConfig {
+                        dual_rate: true, ..
+                    }),
+                ..
+            }) => self.sample_rate * 2,
+            _ => match self.sbr {
+                Some(sbr) if sbr.present => sbr.sample_rate,
+                _ => self.sample_rate,
+            },
+        }
+    }
+
+    /// Number of decoded samples per channel in each frame, or `None` if the object type isn't
+    /// parsed or the config is invalid. Implicit SBR isn't taken into account.
+    pub fn samples_per_frame(&self) -> Option<u32> {
+        let samples: u32 = match &self.specific_config {
+            SpecificConfig::Usac(config) => {
+                return USAC_FRAME_LENGTHS
+                    .get(config.core_sbr_frame_length_index as usize)
+                    .map(|samples| *samples as u32);
+            }
+            SpecificConfig::Eld(config) if config.frame_length_flag => 480,
+            SpecificConfig::Eld(_) => 512,
+            SpecificConfig::Ga(config) => match (self.object_type, config.frame_length_flag) {
+                (M4AType::ER_AAC_LD, true) => 480,
+                (M4AType::ER_AAC_LD, false) => 512,
+                (_, true) => 960,
+                (_, false) => 1024,
+            },
+            SpecificConfig::Other(_) => return None,
+        };
+        // SBR scales the frame length by the same factor as the sample rate
+        (u64::from(samples) * u64::from(self.output_sample_rate()))
+            .checked_div(u64::from(self.sample_rate))
+            .map(|samples| samples as u32)
+    }
+
+    /// Channel layout of the decoded audio, or `None` if the channel configuration is unknown.
+    /// Parametric stereo isn't taken into account.
+    pub fn channels(&self) -> Option<Channels> {
+        match &self.specific_config {
+            SpecificConfig::Usac(config) => config.channels(),
+            SpecificConfig::Ga(GaSpecificConfig {
+                program_config: Some(pce),
+                ..
+            }) => Some(pce.channels()),
+            _ => config_channels(self.channel_config),
+        }
+    }
+}
+
+/// Channel layout of a channel configuration index.
+fn config_channels(channel_config: u8) -> Option<Channels> {
+    match channel_config {
+        11 => Some(layouts::CHANNEL_LAYOUT_AAC_6P1),
+        12 => Some(Channels::Positioned(
+            Position::FRONT_CENTER
+                | Position::FRONT_LEFT
+                | Position::FRONT_RIGHT
+                | Position::SIDE_LEFT
+                | Position::SIDE_RIGHT
+                | Position::REAR_LEFT
+                | Position::REAR_RIGHT
+                | Position::LFE1,
+        )),
+        14 => Some(Channels::Positioned(
+            Position::FRONT_CENTER
+                | Position::FRONT_LEFT
+                | Position::FRONT_RIGHT
+                | Position::REAR_LEFT
+                | Position::REAR_RIGHT
+                | Position::LFE1
+                | Position::TOP_FRONT_LEFT
+                | Position::TOP_FRONT_RIGHT,
+        )),
+        idx => map_to_channels(*AAC_CHANNELS.get(idx as usize)? as u8),
+    }
+}
+
+fn read_sync_extension<B: ReadBitsLtr + FiniteBitStream>(
+    bs: &mut B,
+    sample_rate: u32,
+    end: u64,
+) -> Result<Option<SbrConfig>> {
+    if bs.read_bits_leq32(11)? != SBR_SYNC_WORD {
+        return Ok(None);
+    }
+    // BSAC can also signal its extension here, which isn't supported
+    if read_object_type(bs)? != M4AType::Sbr {
+        return Ok(None);
+    }
+
+    let mut sbr = SbrConfig {
+        signaling: SbrSignaling::BackwardCompatible,
+        present: bs.read_bool()?,
+        sample_rate,
+        ps: None,
+    };
+    if sbr.present {
+        sbr.sample_rate = read_sample_rate(bs)?;
+        if bs.bits_left() >= end + 12 && bs.read_bits_leq32(11)? == PS_SYNC_WORD {
+            sbr.ps = Some(bs.read_bool()?);
+        }
+    }
+    Ok(Some(sbr))
+}
+
+pub(crate) fn read_object_type<B: ReadBitsLtr>(bs: &mut B) -> Result<M4AType> {
+    let otypeidx = match bs.read_bits_leq32(5)? {
+        idx if idx < ESCAPE_OBJECT_TYPE => idx as usize,
+        _ => (bs.read_bits_leq32(6)? + 32) as usize,
+    };
+    Ok(M4A_TYPES.get(otypeidx).copied().unwrap_or(M4AType::Unknown))
+}
+
+fn write_object_type(bw: &mut BitWriter, object_type: M4AType) {
+    // Unknown types can't be written back, so they're written as reserved instead
+    let idx = M4A_TYPES
+        .iter()
+        .position(|ty| *ty == object_type)
+        .unwrap_or(10) as u32;
+    if idx < ESCAPE_OBJECT_TYPE {
+        bw.write_bits(idx, 5);
+    } else {
+        bw.write_bits(ESCAPE_OBJECT_TYPE, 5);
+        bw.write_bits(idx - 32, 6);
+    }
+}
+
+fn read_sample_rate<B: ReadBitsLtr>(bs: &mut B) -> Result<u32> {
+    match bs.read_bits_leq32(4)? {
+        ESCAPE_SAMPLE_RATE_INDEX => Ok(bs.read_bits_leq32(24)?),
+        idx => Ok(AAC_SAMPLE_RATES[idx as usize]),
+    }
+}
+
+fn write_sample_rate(bw: &mut BitWriter, sample_rate: u32) {
+    match AAC_SAMPLE_RATES.iter().position(|s| *s == sample_rate) {
+        Some(idx) if sample_rate > 0 => bw.write_bits(idx as u32, 4),
+        _ => {
+            bw.write_bits(ESCAPE_SAMPLE_RATE_INDEX, 4);
+            bw.write_bits(sample_rate, 24);
+        }
+    }
+}
+
+fn read_escaped_value<B: ReadBitsLtr>(
+    bs: &mut B,
+    bits_1: u32,
+    bits_2: u32,
+    bits_3: u32,
+) -> Result<u32> {
+    let mut value = bs.read_bits_leq32(bits_1)?;
+    if value == (1 << bits_1) - 1 {
+        let value_add = bs.read_bits_leq32(bits_2)?;
+        value += value_add;
+        if value_add == (1 << bits_2) - 1 {
+            value += bs.read_bits_leq32(bits_3)?;
+        }
+    }
+    Ok(value)
+}
+
+fn write_escaped_value(bw: &mut BitWriter, value: u32, bits_1: u32, bits_2: u32, bits_3: u32) {
+    let max_1 = (1 << bits_1) - 1;
+    let max_2 = (1 << bits_2) - 1;
+    if value < max_1 {
+        bw.write_bits(value, bits_1);
+    } else if value - max_1 < max_2 {
+        bw.write_bits(max_1, bits_1);
+        bw.write_bits(value - max_1, bits_2);
+    } else {
+        bw.write_bits(max_1, bits_1);
+        bw.write_bits(max_2, bits_2);
+        bw.write_bits(value - max_1 - max_2, bits_3);
+    }
+}
+
+fn is_general_audio(object_type: M4AType) -> bool {
+    matches!(
+        object_type,
+        M4AType::Main
+            | M4AType::Lc
+            | M4AType::Ssr
+            | M4AType::Ltp
+            | M4AType::Scalable
+            | M4AType::TwinVQ
+            | M4AType::ER_AAC_LC
+            | M4AType::ER_AAC_LTP
+            | M4AType::ER_AAC_Scalable
+            | M4AType::ER_TwinVQ
+            | M4AType::ER_AAC_LD
+    )
+}
+
+fn is_scalable(object_type: M4AType) -> bool {
+    matches!(object_type, M4AType::Scalable | M4AType::ER_AAC_Scalable)
+}
+
+/// Object types with error resilience tools in the GASpecificConfig.
+fn has_resilience_tools(object_type: M4AType) -> bool {
+    matches!(
+        object_type,
+        M4AType::ER_AAC_LC | M4AType::ER_AAC_LTP | M4AType::ER_AAC_Scalable | M4AType::ER_AAC_LD
+    )
+}
+
+fn is_error_resilient(object_type: M4AType) -> bool {
+    matches!(
+        object_type,
+        M4AType::ER_AAC_LC
+            | M4AType::ER_AAC_LTP
+            | M4AType::ER_AAC_Scalable
+            | M4AType::ER_TwinVQ
+            | M4AType::ER_BSAC
+            | M4AType::ER_AAC_LD
+            | M4AType::ER_CELP
+            | M4AType::ER_HVXC
+            | M4AType::ER_HILN
+            | M4AType::ER_Parametric
+            | M4AType::ER_AAC_ELD
+    )
+}
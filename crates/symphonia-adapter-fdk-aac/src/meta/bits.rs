@@ -0,0 +1,78 @@
+use symphonia_core::errors::Result;
+use symphonia_core::io::ReadBitsLtr;
+
+/// Writes bits from most-significant to least-significant, the inverse of
+/// [`BitReaderLtr`](symphonia_core::io::BitReaderLtr).
+#[derive(Debug, Default)]
+pub(crate) struct BitWriter {
+    buf: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    pub(crate) fn write_bits(&mut self, value: u32, bits: u32) {
+        for i in (0..bits).rev() {
+            self.write_bool((value >> i) & 1 != 0);
+        }
+    }
+
+    pub(crate) fn write_bool(&mut self, bit: bool) {
+        if self.len.is_multiple_of(8) {
+            self.buf.push(0);
+        }
+        if bit {
+            let last = self.buf.len() - 1;
+            self.buf[last] |= 0x80 >> (self.len % 8);
+        }
+        self.len += 1;
+    }
+
+    /// Pads with zeros up to the next byte boundary.
+    pub(crate) fn byte_align(&mut self) {
+        self.len = self.buf.len() * 8;
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Part of a config that isn't parsed, kept as raw bits so that it can be written back unchanged.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RawConfig {
+    /// The bits, left-aligned in the last byte.
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl RawConfig {
+    pub(crate) fn read<B: ReadBitsLtr>(bs: &mut B, len: u64) -> Result<Self> {
+        let len = len as usize;
+        let mut bytes = Vec::with_capacity(len.div_ceil(8));
+        for _ in 0..len / 8 {
+            bytes.push(bs.read_bits_leq32(8)? as u8);
+        }
+        let rem = (len % 8) as u32;
+        if rem > 0 {
+            bytes.push((bs.read_bits_leq32(rem)? << (8 - rem)) as u8);
+        }
+        Ok(Self { bytes, len })
+    }
+
+    pub(crate) fn write(&self, bw: &mut BitWriter) {
+        for (i, byte) in self.bytes.iter().enumerate() {
+            let bits = (self.len - i * 8).min(8) as u32;
+            bw.write_bits(u32::from(*byte >> (8 - bits)), bits);
+        }
+    }
+
+    /// Length of the config in bits.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the config is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
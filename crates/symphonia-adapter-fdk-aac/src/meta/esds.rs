@@ -0,0 +1,86 @@
+use symphonia_core::errors::{Result, decode_error};
+
+const ES_DESCRIPTOR_TAG: u8 = 0x03;
+const DECODER_CONFIG_DESCRIPTOR_TAG: u8 = 0x04;
+const DECODER_SPECIFIC_INFO_TAG: u8 = 0x05;
+/// Object type indication, stream type, buffer size and bitrates.
+const DECODER_CONFIG_LEN: usize = 13;
+
+/// Returns the DecoderSpecificInfo from extra data that contains an `esds` box or one of the
+/// MPEG-4 descriptors inside it. Other data is returned unchanged.
+///
+/// None of the descriptor tags are valid as the first byte of an AudioSpecificConfig, since they
+/// would start with object type 0.
+pub(crate) fn unwrap_esds(mut buf: &[u8]) -> Result<&[u8]> {
+    // Box size and type
+    if buf.get(4..8) == Some(b"esds") {
+        buf = &buf[8..];
+    }
+    // Version and flags of the full box
+    if buf.len() > 4 && buf[..4] == [0; 4] && buf[4] == ES_DESCRIPTOR_TAG {
+        buf = &buf[4..];
+    }
+
+    let mut wrapped = false;
+    loop {
+        match buf.first() {
+            Some(&ES_DESCRIPTOR_TAG) => buf = es_descriptor_body(descriptor_body(buf)?)?,
+            Some(&DECODER_CONFIG_DESCRIPTOR_TAG) => {
+                let body = descriptor_body(buf)?;
+                if body.len() < DECODER_CONFIG_LEN {
+                    return decode_error("aac: invalid decoder config descriptor");
+                }
+                buf = &body[DECODER_CONFIG_LEN..];
+            }
+            Some(&DECODER_SPECIFIC_INFO_TAG) => return descriptor_body(buf),
+            _ if wrapped => return decode_error("aac: missing decoder specific info"),
+            _ => return Ok(buf),
+        }
+        wrapped = true;
+    }
+}
+
+/// Returns the contents of the descriptor at the start of `buf`. Sizes that run past the end of
+/// the data are truncated, since some muxers write them incorrectly.
+fn descriptor_body(buf: &[u8]) -> Result<&[u8]> {
+    // The size is stored in up to 4 bytes, with the top bit set on all but the last
+    let mut size = 0;
+    let mut header_len = 1;
+    loop {
+        let Some(byte) = buf.get(header_len) else {
+            return decode_error("aac: truncated descriptor");
+        };
+        size = (size << 7) | usize::from(byte & 0x7f);
+        header_len += 1;
+        if byte & 0x80 == 0 || header_len == 5 {
+            break;
+        }
+    }
+    let body = &buf[header_len..];
+    Ok(&body[..size.min(body.len())])
+}
+
+/// Skips the fields of an ES_Descriptor that come before the DecoderConfigDescriptor.
+fn es_descriptor_body(body: &[u8]) -> Result<&[u8]> {
+    let Some(&flags) = body.get(2) else {
+        return decode_error("aac: invalid es descriptor");
+    };
+    // ES ID and flags
+    let mut pos = 3;
+    // Dependent ES ID
+    if flags & 0x80 != 0 {
+        pos += 2;
+    }
+    // URL
+    if flags & 0x40 != 0 {
+        pos += 1 + usize::from(body.get(pos).copied().unwrap_or_default());
+    }
+    // OCR ES ID
+    if flags & 0x20 != 0 {
+        pos += 2;
+    }
+    match body.get(pos..) {
+        Some(rest) => Ok(rest),
+        None => decode_error("aac: invalid es descriptor"),
+    }
+}
@@ -1,82 +1,79 @@
+//! MPEG-4 audio config parsing.
+
 use std::fmt::{Display, Formatter};
 
 use symphonia_core::audio::{Channels, layouts};
 use symphonia_core::errors::Result;
-use symphonia_core::io::{BitReaderLtr, ReadBitsLtr};
+use symphonia_core::io::{BitReaderLtr, FiniteBitStream, ReadBitsLtr};
 
+pub use self::asc::{
+    AudioSpecificConfig, EldSpecificConfig, ErrorResilience, GaSpecificConfig, LdSbrConfig,
+    SbrConfig, SbrSignaling, SpecificConfig, UsacConfig,
+};
+pub use self::bits::RawConfig;
+pub(crate) use self::esds::unwrap_esds;
+pub use self::pce::{ChannelElement, CouplingElement, MatrixMixdown, ProgramConfig};
 use crate::macros::validate;
 
+mod asc;
+mod bits;
+mod esds;
+mod pce;
+
 // Largely copied from https://github.com/pdeljanov/Symphonia/blob/505458eb1e479d84df0a65f95ab3d536d6350d29/symphonia-codec-aac/src/aac/mod.rs
 // As Symphonia is licensed under MPL-2.0, this file is as well. See LICENSE in this folder
 
-#[derive(Default, Debug)]
+/// Stream configuration used to set up the decoder before the first frame is decoded.
+#[derive(Default, Debug, PartialEq, Eq)]
 pub(crate) struct M4AInfo {
     pub(crate) otype: M4AType,
+    /// Sample rate of the decoded audio.
     pub(crate) sample_rate: u32,
+    /// Index of the core sample rate, as used by ADTS headers.
     pub(crate) sample_rate_index: u8,
     pub(crate) channels: u8,
+    /// Layout of the decoded audio, if the config describes it.
+    pub(crate) layout: Option<Channels>,
     pub(crate) samples: usize,
 }
 
 impl M4AInfo {
-    fn read_object_type<B: ReadBitsLtr>(bs: &mut B) -> Result<M4AType> {
-        let otypeidx = match bs.read_bits_leq32(5)? {
-            idx if idx < 31 => idx as usize,
-            31 => (bs.read_bits_leq32(6)? + 32) as usize,
-            _ => unreachable!(),
-        };
-
-        if otypeidx >= M4A_TYPES.len() {
-            Ok(M4AType::Unknown)
-        } else {
-            Ok(M4A_TYPES[otypeidx])
+    pub(crate) fn from_config(asc: &AudioSpecificConfig) -> Self {
+        let layout = asc.channels();
+        Self {
+            otype: asc.object_type,
+            sample_rate: asc.output_sample_rate(),
+            sample_rate_index: sample_rate_index(asc.sample_rate),
+            channels: layout.as_ref().map_or(0, |layout| layout.count() as u8),
+            layout,
+            samples: asc.samples_per_frame().unwrap_or(1024) as usize,
         }
     }
 
-    fn read_sampling_frequency<B: ReadBitsLtr>(bs: &mut B) -> Result<u32> {
-        match bs.read_bits_leq32(4)? {
-            idx if idx < 15 => Ok(AAC_SAMPLE_RATES[idx as usize]),
-            _ => {
-                let srate = (0xf << 20) & bs.read_bits_leq32(20)?;
-                Ok(srate)
-            }
+    /// Reads the AudioSpecificConfig of the first program and layer from a LATM
+    /// StreamMuxConfig. Returns the number of payloads in each AudioMuxElement.
+    pub(crate) fn read_stream_mux_config(&mut self, bs: &mut BitReaderLtr<'_>) -> Result<u32> {
+        let audio_mux_version = bs.read_bool()?;
+        // Version A 1 is reserved
+        validate!(!(audio_mux_version && bs.read_bool()?));
+        if audio_mux_version {
+            let _tara_buffer_fullness = read_latm_value(bs)?;
         }
-    }
 
-    fn read_channel_config<B: ReadBitsLtr>(bs: &mut B) -> Result<usize> {
-        let chidx = bs.read_bits_leq32(4)? as usize;
-        if chidx < AAC_CHANNELS.len() {
-            Ok(AAC_CHANNELS[chidx])
+        let _all_streams_same_time_framing = bs.read_bool()?;
+        let num_sub_frames = bs.read_bits_leq32(6)?;
+        let _num_program = bs.read_bits_leq32(4)?;
+        let _num_layer = bs.read_bits_leq32(3)?;
+        // The length of the config is only known in version 1
+        let end = if audio_mux_version {
+            let asc_len = read_latm_value(bs)?;
+            bs.bits_left().saturating_sub(asc_len.into())
         } else {
-            Ok(chidx)
-        }
-    }
-
-    pub(crate) fn read(&mut self, buf: &[u8]) -> Result<()> {
-        let mut bs = BitReaderLtr::new(buf);
-
-        self.otype = Self::read_object_type(&mut bs)?;
-        self.sample_rate = Self::read_sampling_frequency(&mut bs)?;
-        self.sample_rate_index = sample_rate_index(self.sample_rate);
-
-        validate!(self.sample_rate > 0);
-
-        self.channels = Self::read_channel_config(&mut bs)? as u8;
-
-        if (self.otype == M4AType::Sbr) || (self.otype == M4AType::PS) {
-            let _ext_srate = Self::read_sampling_frequency(&mut bs)?;
-            self.otype = Self::read_object_type(&mut bs)?;
-
-            let _ext_chans = if self.otype == M4AType::ER_BSAC {
-                Self::read_channel_config(&mut bs)?
-            } else {
-                0
-            };
-        }
-        let short_frame = bs.read_bool()?;
-        self.samples = if short_frame { 960 } else { 1024 };
+            bs.bits_left()
+        };
+        *self = Self::from_config(&AudioSpecificConfig::read_bits(bs, end)?);
 
-        Ok(())
+        Ok(num_sub_frames + 1)
     }
 }
 
@@ -90,49 +87,92 @@ impl Display for M4AInfo {
     }
 }
 
+/// MPEG-4 audio object type.
 #[allow(non_camel_case_types)]
 #[derive(Clone, Default, Copy, Debug, PartialEq, Eq)]
-pub(crate) enum M4AType {
+pub enum M4AType {
     #[default]
+    /// No object type.
     None,
+    /// AAC Main.
     Main,
+    /// AAC Low Complexity.
     Lc,
+    /// AAC Scalable Sample Rate.
     Ssr,
+    /// AAC Long Term Prediction.
     Ltp,
+    /// Spectral Band Replication, used by HE-AAC.
     Sbr,
+    /// AAC Scalable.
     Scalable,
+    /// TwinVQ.
     TwinVQ,
+    /// Code Excited Linear Prediction.
     Celp,
+    /// Harmonic Vector Excitation Coding.
     Hvxc,
+    /// Text-To-Speech Interface.
     Ttsi,
+    /// Main synthesis.
     MainSynth,
+    /// Wavetable synthesis.
     WavetableSynth,
+    /// General MIDI.
     GeneralMIDI,
+    /// Algorithmic synthesis and audio effects.
     Algorithmic,
+    /// Error resilient AAC Low Complexity.
     ER_AAC_LC,
+    /// Error resilient AAC Long Term Prediction.
     ER_AAC_LTP,
+    /// Error resilient AAC Scalable.
     ER_AAC_Scalable,
+    /// Error resilient TwinVQ.
     ER_TwinVQ,
+    /// Error resilient Bit-Sliced Arithmetic Coding.
     ER_BSAC,
+    /// Error resilient AAC Low Delay.
     ER_AAC_LD,
+    /// Error resilient CELP.
     ER_CELP,
+    /// Error resilient HVXC.
     ER_HVXC,
+    /// Error resilient Harmonic and Individual Lines plus Noise.
     ER_HILN,
+    /// Error resilient parametric audio.
     ER_Parametric,
+    /// SinuSoidal Coding.
     Ssc,
+    /// Parametric Stereo, used by HE-AAC v2.
     PS,
+    /// MPEG Surround.
     MPEGSurround,
+    /// MPEG-1 Layer 1.
     Layer1,
+    /// MPEG-1 Layer 2.
     Layer2,
+    /// MPEG-1 Layer 3.
     Layer3,
+    /// Direct Stream Transfer.
     Dst,
+    /// Audio Lossless Coding.
     Als,
+    /// Scalable Lossless Coding.
     Sls,
+    /// Scalable Lossless Coding without a core.
     SLSNonCore,
+    /// Error resilient AAC Enhanced Low Delay.
     ER_AAC_ELD,
+    /// Symbolic Music Representation Simple.
     SMRSimple,
+    /// Symbolic Music Representation Main.
     SMRMain,
+    /// Unified Speech and Audio Coding, used by xHE-AAC.
+    Usac,
+    /// A reserved object type.
     Reserved,
+    /// An object type that isn't known.
     Unknown,
 }
 
@@ -179,6 +219,7 @@ pub(crate) const M4A_TYPES: &[M4AType] = &[
     M4AType::ER_AAC_ELD,
     M4AType::SMRSimple,
     M4AType::SMRMain,
+    M4AType::Usac,
 ];
 
 impl Display for M4AType {
@@ -231,15 +272,26 @@ pub(crate) const M4A_TYPE_NAMES: &[&str] = &[
     "ER AAC ELD",
     "SMR Simple",
     "SMR Main",
+    "USAC",
     "(reserved)",
     "(unknown)",
 ];
 
-const AAC_SAMPLE_RATES: [u32; 16] = [
+pub(crate) const AAC_SAMPLE_RATES: [u32; 16] = [
     96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350, 0, 0,
     0,
 ];
 
+/// The USAC table extends the AAC one with additional rates.
+pub(crate) const USAC_SAMPLE_RATES: [u32; 31] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350, 0, 0,
+    57600, 51200, 40000, 38400, 34150, 28800, 25600, 20000, 19200, 17075, 14400, 12800, 9600, 0, 0,
+    0,
+];
+
+/// Output frame length for each `coreSbrFrameLengthIndex`, including SBR upsampling.
+pub(crate) const USAC_FRAME_LENGTHS: [usize; 5] = [768, 1024, 2048, 2048, 4096];
+
 pub(crate) fn sample_rate_index(sample_rate: u32) -> u8 {
     AAC_SAMPLE_RATES
         .iter()
@@ -247,7 +299,13 @@ pub(crate) fn sample_rate_index(sample_rate: u32) -> u8 {
         .unwrap_or_default() as u8
 }
 
-const AAC_CHANNELS: [usize; 8] = [0, 1, 2, 3, 4, 5, 6, 8];
+/// Reads a LatmGetValue() field, which stores its own length in bytes.
+fn read_latm_value<B: ReadBitsLtr>(bs: &mut B) -> Result<u32> {
+    let bytes = bs.read_bits_leq32(2)? + 1;
+    Ok(bs.read_bits_leq32(bytes * 8)?)
+}
+
+pub(crate) const AAC_CHANNELS: [usize; 8] = [0, 1, 2, 3, 4, 5, 6, 8];
 
 pub(crate) fn map_to_channels(num_channels: u8) -> Option<Channels> {
     let channels = match num_channels {
@@ -257,7 +315,7 @@ pub(crate) fn map_to_channels(num_channels: u8) -> Option<Channels> {
         4 => layouts::CHANNEL_LAYOUT_AAC_4P0,
         5 => layouts::CHANNEL_LAYOUT_AAC_5P0,
         6 => layouts::CHANNEL_LAYOUT_AAC_5P1,
-        7 => layouts::CHANNEL_LAYOUT_AAC_7P1,
+        8 => layouts::CHANNEL_LAYOUT_AAC_7P1,
         _ => return None,
     };
 
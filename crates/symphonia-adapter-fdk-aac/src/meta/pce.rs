@@ -0,0 +1,238 @@
+use fdk_aac_sys as sys;
+use symphonia_core::audio::{Channels, Position};
+use symphonia_core::errors::Result;
+use symphonia_core::io::{FiniteBitStream, ReadBitsLtr};
+
+use super::bits::BitWriter;
+use crate::channels::positions;
+
+/// A single or paired channel element of a program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelElement {
+    /// The element is a channel pair element (CPE) rather than a single channel element (SCE).
+    pub is_cpe: bool,
+    /// Instance tag of the element.
+    pub tag: u8,
+}
+
+/// A coupling channel element of a program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CouplingElement {
+    /// The coupling channel is independently switched.
+    pub independently_switched: bool,
+    /// Instance tag of the element.
+    pub tag: u8,
+}
+
+/// Matrix downmix of the front and surround channels to stereo.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MatrixMixdown {
+    /// Index of the surround mixdown coefficient.
+    pub index: u8,
+    /// Use pseudo surround instead of a plain stereo downmix.
+    pub pseudo_surround: bool,
+}
+
+/// Program config element (PCE), which describes the channels of streams with channel
+/// configuration 0.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProgramConfig {
+    /// Instance tag of the element.
+    pub element_instance_tag: u8,
+    /// Object type of the program, in the 2 bit ADTS numbering.
+    pub object_type: u8,
+    /// Sample rate index of the program.
+    pub sample_rate_index: u8,
+    /// Front channel elements, from the center outwards.
+    pub front_elements: Vec<ChannelElement>,
+    /// Side channel elements, from the front to the back.
+    pub side_elements: Vec<ChannelElement>,
+    /// Back channel elements, from the outside inwards.
+    pub back_elements: Vec<ChannelElement>,
+    /// Instance tags of the LFE elements.
+    pub lfe_elements: Vec<u8>,
+    /// Instance tags of the associated data elements.
+    pub assoc_data_elements: Vec<u8>,
+    /// Coupling channel elements.
+    pub cc_elements: Vec<CouplingElement>,
+    /// Instance tag of the SCE to use for a mono downmix.
+    pub mono_mixdown: Option<u8>,
+    /// Instance tag of the CPE to use for a stereo downmix.
+    pub stereo_mixdown: Option<u8>,
+    /// Matrix downmix to stereo.
+    pub matrix_mixdown: Option<MatrixMixdown>,
+    /// Free-form comment.
+    pub comment: Vec<u8>,
+}
+
+impl ProgramConfig {
+    /// Reads a PCE. Its byte alignment is relative to the start of the AudioSpecificConfig,
+    /// which is where `start` bits were left in the reader.
+    pub(crate) fn read<B: ReadBitsLtr + FiniteBitStream>(bs: &mut B, start: u64) -> Result<Self> {
+        let mut pce = Self {
+            element_instance_tag: bs.read_bits_leq32(4)? as u8,
+            object_type: bs.read_bits_leq32(2)? as u8,
+            sample_rate_index: bs.read_bits_leq32(4)? as u8,
+            ..Default::default()
+        };
+        let num_front = bs.read_bits_leq32(4)?;
+        let num_side = bs.read_bits_leq32(4)?;
+        let num_back = bs.read_bits_leq32(4)?;
+        let num_lfe = bs.read_bits_leq32(2)?;
+        let num_assoc_data = bs.read_bits_leq32(3)?;
+        let num_cc = bs.read_bits_leq32(4)?;
+
+        if bs.read_bool()? {
+            pce.mono_mixdown = Some(bs.read_bits_leq32(4)? as u8);
+        }
+        if bs.read_bool()? {
+            pce.stereo_mixdown = Some(bs.read_bits_leq32(4)? as u8);
+        }
+        if bs.read_bool()? {
+            pce.matrix_mixdown = Some(MatrixMixdown {
+                index: bs.read_bits_leq32(2)? as u8,
+                pseudo_surround: bs.read_bool()?,
+            });
+        }
+
+        pce.front_elements = read_channel_elements(bs, num_front)?;
+        pce.side_elements = read_channel_elements(bs, num_side)?;
+        pce.back_elements = read_channel_elements(bs, num_back)?;
+        pce.lfe_elements = read_tags(bs, num_lfe)?;
+        pce.assoc_data_elements = read_tags(bs, num_assoc_data)?;
+        pce.cc_elements = (0..num_cc)
+            .map(|_| {
+                Ok(CouplingElement {
+                    independently_switched: bs.read_bool()?,
+                    tag: bs.read_bits_leq32(4)? as u8,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let read = start - bs.bits_left();
+        bs.ignore_bits(((8 - read % 8) % 8) as u32)?;
+        let comment_len = bs.read_bits_leq32(8)?;
+        pce.comment = read_values(bs, comment_len, 8)?;
+        Ok(pce)
+    }
+
+    /// Writes the PCE. The writer must start at the start of the AudioSpecificConfig.
+    pub(crate) fn write(&self, bw: &mut BitWriter) {
+        bw.write_bits(u32::from(self.element_instance_tag), 4);
+        bw.write_bits(u32::from(self.object_type), 2);
+        bw.write_bits(u32::from(self.sample_rate_index), 4);
+        bw.write_bits(self.front_elements.len() as u32, 4);
+        bw.write_bits(self.side_elements.len() as u32, 4);
+        bw.write_bits(self.back_elements.len() as u32, 4);
+        bw.write_bits(self.lfe_elements.len() as u32, 2);
+        bw.write_bits(self.assoc_data_elements.len() as u32, 3);
+        bw.write_bits(self.cc_elements.len() as u32, 4);
+
+        bw.write_bool(self.mono_mixdown.is_some());
+        if let Some(tag) = self.mono_mixdown {
+            bw.write_bits(u32::from(tag), 4);
+        }
+        bw.write_bool(self.stereo_mixdown.is_some());
+        if let Some(tag) = self.stereo_mixdown {
+            bw.write_bits(u32::from(tag), 4);
+        }
+        bw.write_bool(self.matrix_mixdown.is_some());
+        if let Some(mixdown) = self.matrix_mixdown {
+            bw.write_bits(u32::from(mixdown.index), 2);
+            bw.write_bool(mixdown.pseudo_surround);
+        }
+
+        for element in self
+            .front_elements
+            .iter()
+            .chain(&self.side_elements)
+            .chain(&self.back_elements)
+        {
+            bw.write_bool(element.is_cpe);
+            bw.write_bits(u32::from(element.tag), 4);
+        }
+        for tag in self.lfe_elements.iter().chain(&self.assoc_data_elements) {
+            bw.write_bits(u32::from(*tag), 4);
+        }
+        for element in &self.cc_elements {
+            bw.write_bool(element.independently_switched);
+            bw.write_bits(u32::from(element.tag), 4);
+        }
+
+        bw.byte_align();
+        bw.write_bits(self.comment.len() as u32, 8);
+        for byte in &self.comment {
+            bw.write_bits(u32::from(*byte), 8);
+        }
+    }
+
+    /// Total number of output channels.
+    pub fn num_channels(&self) -> usize {
+        let count = |elements: &[ChannelElement]| {
+            elements
+                .iter()
+                .map(|element| if element.is_cpe { 2 } else { 1 })
+                .sum::<usize>()
+        };
+        count(&self.front_elements)
+            + count(&self.side_elements)
+            + count(&self.back_elements)
+            + self.lfe_elements.len()
+    }
+
+    /// Channel layout of the program. Layouts that can't be represented by Symphonia's positions
+    /// are returned as discrete channels.
+    pub fn channels(&self) -> Channels {
+        // Number the channels the same way FDK does in its stream info
+        let mut types = Vec::new();
+        let mut indices = Vec::new();
+        for (elements, ty) in [
+            (&self.front_elements, sys::AUDIO_CHANNEL_TYPE_ACT_FRONT),
+            (&self.side_elements, sys::AUDIO_CHANNEL_TYPE_ACT_SIDE),
+            (&self.back_elements, sys::AUDIO_CHANNEL_TYPE_ACT_BACK),
+        ] {
+            let mut idx = 0;
+            for element in elements {
+                for _ in 0..if element.is_cpe { 2 } else { 1 } {
+                    types.push(ty);
+                    indices.push(idx);
+                    idx += 1;
+                }
+            }
+        }
+        for idx in 0..self.lfe_elements.len() {
+            types.push(sys::AUDIO_CHANNEL_TYPE_ACT_LFE);
+            indices.push(idx as u8);
+        }
+
+        match positions(&types, &indices) {
+            Some(positions) if !positions.is_empty() => Channels::Positioned(
+                positions
+                    .iter()
+                    .fold(Position::empty(), |acc, pos| acc.union(*pos)),
+            ),
+            _ => Channels::Discrete(types.len() as u16),
+        }
+    }
+}
+
+fn read_channel_elements<B: ReadBitsLtr>(bs: &mut B, count: u32) -> Result<Vec<ChannelElement>> {
+    (0..count)
+        .map(|_| {
+            Ok(ChannelElement {
+                is_cpe: bs.read_bool()?,
+                tag: bs.read_bits_leq32(4)? as u8,
+            })
+        })
+        .collect()
+}
+
+fn read_tags<B: ReadBitsLtr>(bs: &mut B, count: u32) -> Result<Vec<u8>> {
+    read_values(bs, count, 4)
+}
+
+fn read_values<B: ReadBitsLtr>(bs: &mut B, count: u32, bits: u32) -> Result<Vec<u8>> {
+    (0..count)
+        .map(|_| Ok(bs.read_bits_leq32(bits)? as u8))
+        .collect()
+}
@@ -0,0 +1,100 @@
+use std::fmt::{self, Display, Formatter};
+
+use fdk_aac_sys as sys;
+
+use crate::meta::{M4A_TYPES, M4AType};
+
+/// Snapshot of the stream as seen by the decoder. This reflects the last decoded frame, so it
+/// can change mid-stream.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AacStreamInfo {
+    /// Audio object type of the core stream.
+    pub object_type: M4AType,
+    /// Extension object type, such as SBR, if it's explicitly signaled in the config.
+    pub extension_object_type: Option<M4AType>,
+    /// Sample rate of the decoded audio.
+    pub sample_rate: u32,
+    /// Sample rate of the AAC core, without SBR.
+    pub core_sample_rate: u32,
+    /// Sample rate of the extension, if it's explicitly signaled in the config.
+    pub extension_sample_rate: Option<u32>,
+    /// Number of channels of the decoded audio.
+    pub channels: u32,
+    /// Number of channels of the AAC core, before PS or MPEG Surround upmixing.
+    pub core_channels: u32,
+    /// Channel configuration from the config, where 0 means that it's defined by a PCE.
+    pub channel_config: u8,
+    /// SBR is present, either explicitly signaled or detected in the stream.
+    pub sbr_present: bool,
+    /// Parametric stereo is present, either explicitly signaled or detected in the stream.
+    pub ps_present: bool,
+    /// Instantaneous bitrate in bits per second.
+    pub bitrate: u32,
+    /// Samples per channel in each decoded frame.
+    pub frame_size: u32,
+    /// Samples per channel in each frame of the AAC core.
+    pub core_frame_size: u32,
+    /// Number of samples per channel that the output is delayed by.
+    pub output_delay: u32,
+    /// Estimated number of frames that were lost at the last transport sync error.
+    pub lost_frames: Option<u32>,
+    /// Number of frames that have passed through the decoder.
+    pub total_frames: u64,
+    /// Number of frames that had errors and were concealed.
+    pub bad_frames: u64,
+    /// Number of bytes that have passed through the decoder.
+    pub total_bytes: u64,
+    /// Number of bytes that had errors.
+    pub bad_bytes: u64,
+}
+
+impl AacStreamInfo {
+    pub(crate) fn from_stream_info(info: &sys::CStreamInfo) -> Self {
+        let object_type = |aot: sys::AUDIO_OBJECT_TYPE| {
+            usize::try_from(aot)
+                .ok()
+                .filter(|aot| *aot > 0)
+                .map(|aot| M4A_TYPES.get(aot).copied().unwrap_or(M4AType::Unknown))
+        };
+        Self {
+            object_type: object_type(info.aot).unwrap_or(M4AType::None),
+            extension_object_type: object_type(info.extAot),
+            sample_rate: info.sampleRate.max(0) as u32,
+            core_sample_rate: info.aacSampleRate.max(0) as u32,
+            extension_sample_rate: u32::try_from(info.extSamplingRate)
+                .ok()
+                .filter(|rate| *rate > 0),
+            channels: info.numChannels.max(0) as u32,
+            core_channels: info.aacNumChannels.max(0) as u32,
+            channel_config: info.channelConfig.max(0) as u8,
+            sbr_present: info.flags & sys::AC_SBR_PRESENT != 0,
+            ps_present: info.flags & sys::AC_PS_PRESENT != 0,
+            bitrate: info.bitRate.max(0) as u32,
+            frame_size: info.frameSize.max(0) as u32,
+            core_frame_size: info.aacSamplesPerFrame.max(0) as u32,
+            output_delay: info.outputDelay,
+            lost_frames: u32::try_from(info.numLostAccessUnits).ok(),
+            total_frames: info.numTotalAccessUnits.max(0) as u64,
+            bad_frames: info.numBadAccessUnits.max(0) as u64,
+            total_bytes: info.numTotalBytes.max(0) as u64,
+            bad_bytes: info.numBadBytes.max(0) as u64,
+        }
+    }
+}
+
+impl Display for AacStreamInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "MPEG 4 Audio {}", self.object_type)?;
+        if self.sbr_present {
+            write!(f, " + SBR")?;
+        }
+        if self.ps_present {
+            write!(f, " + PS")?;
+        }
+        write!(
+            f,
+            ", {} Hz, {} channels, {} samples per frame",
+            self.sample_rate, self.channels, self.frame_size
+        )
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+
+use symphonia_core::packet::PacketRef;
+
+/// Timing of a packet that was passed to the decoder, kept until its audio is output.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PendingPacket {
+    pub(crate) pts: i64,
+    pub(crate) dur: u64,
+    pub(crate) trim_start: usize,
+    pub(crate) trim_end: usize,
+}
+
+impl PendingPacket {
+    pub(crate) fn new(packet: &PacketRef) -> Self {
+        Self {
+            pts: packet.pts.get(),
+            dur: packet.dur.get(),
+            trim_start: packet.trim_start.get() as usize,
+            trim_end: packet.trim_end.get() as usize,
+        }
+    }
+
+    /// Splits the packet into `n` frames of equal duration. The start and end trims go to the
+    /// first and last frame.
+    pub(crate) fn split(self, n: usize) -> impl Iterator<Item = Self> {
+        let n = n as u64;
+        (0..n).map(move |i| {
+            let start = self.dur * i / n;
+            let end = self.dur * (i + 1) / n;
+            Self {
+                pts: self.pts + start as i64,
+                dur: end - start,
+                trim_start: if i == 0 { self.trim_start } else { 0 },
+                trim_end: if i == n - 1 { self.trim_end } else { 0 },
+            }
+        })
+    }
+}
+
+/// Decides which samples of the decoder output to keep.
+///
+/// FDK delays its output by `outputDelay` samples, so the audio of a packet isn't fully output
+/// until later packets are decoded. The samples of each packet are queued along with whether to
+/// keep them and matched against the output as it comes out of the decoder.
+#[derive(Debug, Default)]
+pub(crate) struct TrimQueue {
+    /// Runs of samples per channel and whether to keep them, oldest first.
+    runs: VecDeque<(bool, usize)>,
+}
+
+impl TrimQueue {
+    /// Clears the queue and adds the samples the decoder outputs before the first packet.
+    pub(crate) fn reset(&mut self, delay: usize, keep: bool) {
+        self.runs.clear();
+        self.push(keep, delay);
+    }
+
+    /// Adds a packet, trimming the given number of samples from its start and end.
+    pub(crate) fn push_packet(&mut self, len: usize, trim_start: usize, trim_end: usize) {
+        let trim_start = trim_start.min(len);
+        let trim_end = trim_end.min(len - trim_start);
+        self.push(false, trim_start);
+        self.push(true, len - trim_start - trim_end);
+        self.push(false, trim_end);
+    }
+
+    pub(crate) fn push(&mut self, keep: bool, len: usize) {
+        if len == 0 {
+            return;
+        }
+        match self.runs.back_mut() {
+            Some((last_keep, last_len)) if *last_keep == keep => *last_len += len,
+            _ => self.runs.push_back((keep, len)),
+        }
+    }
+
+    /// Removes `len` output samples from the queue and returns the number of them to trim from
+    /// the start and end. Samples past the end of the queue are trimmed.
+    pub(crate) fn pop(&mut self, len: usize) -> (usize, usize) {
+        let mut pos = 0;
+        let mut keep_start = None;
+        let mut keep_end = 0;
+        while pos < len {
+            let Some((keep, run_len)) = self.runs.front_mut() else {
+                break;
+            };
+            let n = (*run_len).min(len - pos);
+            // Trims only occur at the edges of the stream, so anything between the first and
+            // last kept sample is kept as well.
+            if *keep {
+                keep_start.get_or_insert(pos);
+                keep_end = pos + n;
+            }
+            pos += n;
+            *run_len -= n;
+            if *run_len == 0 {
+                self.runs.pop_front();
+            }
+        }
+        match keep_start {
+            Some(start) => (start, len - keep_end),
+            None => (len, 0),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+}
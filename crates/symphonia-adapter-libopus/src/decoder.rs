@@ -3,7 +3,9 @@ use std::ffi::c_int;
 use log::{error, warn};
 use symphonia_core::errors::{Error, Result};
 
-fn error_code_to_str(code: c_int) -> &'static str {
+use crate::ChannelMapping;
+
+pub(crate) fn error_code_to_str(code: c_int) -> &'static str {
     match code {
         opusic_sys::OPUS_BAD_ARG => "One or more invalid/out of range arguments.",
         opusic_sys::OPUS_BUFFER_TOO_SMALL => "The mode struct passed is invalid.",
@@ -20,14 +22,14 @@ fn error_code_to_str(code: c_int) -> &'static str {
 
 #[derive(Debug)]
 pub(crate) struct Decoder {
-    ptr: *mut opusic_sys::OpusDecoder,
+    ptr: *mut opusic_sys::OpusMSDecoder,
     channels: u32,
 }
 
 impl Drop for Decoder {
     fn drop(&mut self) {
         unsafe {
-            opusic_sys::opus_decoder_destroy(self.ptr);
+            opusic_sys::opus_multistream_decoder_destroy(self.ptr);
         }
     }
 }
@@ -36,10 +38,20 @@ unsafe impl Send for Decoder {}
 unsafe impl Sync for Decoder {}
 
 impl Decoder {
-    pub(crate) fn new(sample_rate: u32, channels: u32) -> Result<Self> {
+    pub(crate) fn new(sample_rate: u32, mapping: &ChannelMapping) -> Result<Self> {
+        let channels = mapping.mapping.len() as u32;
         let mut error = 0;
+        // The multistream decoder also handles regular mono and stereo streams, so it's used for
+        // every mapping family.
         let ptr = unsafe {
-            opusic_sys::opus_decoder_create(sample_rate as i32, channels as c_int, &mut error)
+            opusic_sys::opus_multistream_decoder_create(
+                sample_rate as i32,
+                channels as c_int,
+                mapping.streams as c_int,
+                mapping.coupled_streams as c_int,
+                mapping.mapping.as_ptr(),
+                &mut error,
+            )
         };
         if error != opusic_sys::OPUS_OK {
             let error_str = error_code_to_str(error);
@@ -50,18 +62,59 @@ impl Decoder {
     }
 
     pub(crate) fn decode(&mut self, input: &[u8], output: &mut [f32]) -> Result<usize> {
+        let frame_size = len(output)? / self.channels as c_int;
+        self.decode_float(input, output, frame_size, false)
+    }
+
+    /// Recovers `samples_per_channel` samples of audio preceding `input` from its in-band FEC
+    /// data. This must be a multiple of 2.5 ms. If the packet has no FEC data, the audio is
+    /// concealed instead.
+    pub(crate) fn decode_fec(
+        &mut self,
+        input: &[u8],
+        samples_per_channel: usize,
+        output: &mut [f32],
+    ) -> Result<usize> {
+        let frame_size = check_len(samples_per_channel)?;
+        if output.len() < samples_per_channel * self.channels as usize {
+            return Err(Error::DecodeError("opus: output buffer too small"));
+        }
+        self.decode_float(input, output, frame_size, true)
+    }
+
+    /// Runs packet loss concealment for the given number of samples per channel. This must be a
+    /// multiple of 2.5 ms.
+    pub(crate) fn conceal(
+        &mut self,
+        samples_per_channel: usize,
+        output: &mut [f32],
+    ) -> Result<usize> {
+        let frame_size = check_len(samples_per_channel)?;
+        if output.len() < samples_per_channel * self.channels as usize {
+            return Err(Error::DecodeError("opus: output buffer too small"));
+        }
+        self.decode_float(&[], output, frame_size, false)
+    }
+
+    fn decode_float(
+        &mut self,
+        input: &[u8],
+        output: &mut [f32],
+        frame_size: c_int,
+        decode_fec: bool,
+    ) -> Result<usize> {
         let ptr = match input.len() {
             0 => std::ptr::null(),
             _ => input.as_ptr(),
         };
         let len = unsafe {
-            opusic_sys::opus_decode_float(
+            opusic_sys::opus_multistream_decode_float(
                 self.ptr,
                 ptr,
                 len(input)?,
                 output.as_mut_ptr(),
-                len(output)? / self.channels as c_int,
-                0 as c_int,
+                frame_size,
+                decode_fec as c_int,
             )
         };
         if len < 0 {
@@ -72,9 +125,49 @@ impl Decoder {
         Ok(len as usize)
     }
 
+    /// Duration of the last decoded or concealed packet in samples per channel.
+    pub(crate) fn last_packet_duration(&mut self) -> Result<usize> {
+        let mut duration: opusic_sys::opus_int32 = 0;
+        let result = unsafe {
+            opusic_sys::opus_multistream_decoder_ctl(
+                self.ptr,
+                opusic_sys::OPUS_GET_LAST_PACKET_DURATION_REQUEST,
+                &mut duration as *mut opusic_sys::opus_int32,
+            )
+        };
+
+        if result != opusic_sys::OPUS_OK {
+            let error_str = error_code_to_str(result);
+            warn!("getting last packet duration failed with error code {result}: {error_str}");
+            return Err(Error::DecodeError(
+                "opus: error getting last packet duration",
+            ));
+        }
+        Ok(duration.max(0) as usize)
+    }
+
+    /// Sets the gain applied to the decoded output in Q7.8 dB.
+    pub(crate) fn set_gain(&mut self, gain: i16) -> Result<()> {
+        let result = unsafe {
+            opusic_sys::opus_multistream_decoder_ctl(
+                self.ptr,
+                opusic_sys::OPUS_SET_GAIN_REQUEST,
+                gain as c_int,
+            )
+        };
+
+        if result != opusic_sys::OPUS_OK {
+            let error_str = error_code_to_str(result);
+            error!("setting gain failed with error code {result}: {error_str}");
+            return Err(Error::DecodeError("opus: error setting gain"));
+        }
+        Ok(())
+    }
+
     pub(crate) fn reset(&mut self) {
-        let result =
-            unsafe { opusic_sys::opus_decoder_ctl(self.ptr, opusic_sys::OPUS_RESET_STATE) };
+        let result = unsafe {
+            opusic_sys::opus_multistream_decoder_ctl(self.ptr, opusic_sys::OPUS_RESET_STATE)
+        };
 
         if result != opusic_sys::OPUS_OK {
             let error_str = error_code_to_str(result);
@@ -83,7 +176,7 @@ impl Decoder {
     }
 }
 
-fn check_len(val: usize) -> Result<c_int> {
+pub(crate) fn check_len(val: usize) -> Result<c_int> {
     c_int::try_from(val).map_err(|_| {
         error!("buffer length of out range: {val}");
         Error::DecodeError("buffer length of out range")
@@ -91,6 +184,6 @@ fn check_len(val: usize) -> Result<c_int> {
 }
 
 #[inline]
-fn len<T>(slice: &[T]) -> Result<c_int> {
+pub(crate) fn len<T>(slice: &[T]) -> Result<c_int> {
     check_len(slice.len())
 }
@@ -0,0 +1,559 @@
+use std::ffi::c_int;
+use std::fmt;
+
+use log::{error, warn};
+use symphonia_core::audio::{Channels, GenericAudioBufferRef};
+use symphonia_core::errors::{Error, Result, unsupported_error};
+use symphonia_core::packet::Packet;
+use symphonia_core::units::{Duration, Timestamp};
+
+use crate::decoder::{error_code_to_str, len};
+use crate::{ChannelMapping, DEFAULT_SAMPLE_RATE, OpusHead, OpusHeadLayout};
+
+/// Up to six 20 ms frames of at most 1275 bytes each, plus the packet framing.
+const MAX_PACKET_SIZE: usize = 1275 * 6 + 7;
+
+/// Error reported by libopus while creating, configuring or running the encoder.
+///
+/// Symphonia has no error kind for encoding, so this is returned wrapped in
+/// [`Error::IoError`] and can be recovered with [`std::io::Error::get_ref`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EncodeError {
+    /// What the encoder was doing when the error occurred.
+    pub context: &'static str,
+    /// libopus error code, such as `OPUS_BAD_ARG`.
+    pub code: i32,
+}
+
+impl EncodeError {
+    fn new(context: &'static str, code: c_int) -> Self {
+        Self { context, code }
+    }
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "opus: {} ({}): {}",
+            self.context,
+            self.code,
+            error_code_to_str(self.code)
+        )
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+impl From<EncodeError> for Error {
+    fn from(e: EncodeError) -> Self {
+        Error::IoError(std::io::Error::other(e))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Encoder {
+    ptr: *mut opusic_sys::OpusEncoder,
+    channels: u32,
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        unsafe {
+            opusic_sys::opus_encoder_destroy(self.ptr);
+        }
+    }
+}
+
+unsafe impl Send for Encoder {}
+unsafe impl Sync for Encoder {}
+
+impl Encoder {
+    pub(crate) fn new(sample_rate: u32, channels: u32, application: c_int) -> Result<Self> {
+        let mut error = 0;
+        let ptr = unsafe {
+            opusic_sys::opus_encoder_create(
+                sample_rate as i32,
+                channels as c_int,
+                application,
+                &mut error,
+            )
+        };
+        if error != opusic_sys::OPUS_OK {
+            let error_str = error_code_to_str(error);
+            error!("encoder failed to create with error code {error}: {error_str}");
+            return Err(EncodeError::new("error creating encoder", error).into());
+        }
+        Ok(Self { ptr, channels })
+    }
+
+    /// Encodes one frame of interleaved samples. The frame duration is inferred from the input
+    /// length and must be one that Opus supports.
+    pub(crate) fn encode(&mut self, input: &[f32], output: &mut [u8]) -> Result<usize> {
+        let frame_size = len(input)? / self.channels as c_int;
+        let len = unsafe {
+            opusic_sys::opus_encode_float(
+                self.ptr,
+                input.as_ptr(),
+                frame_size,
+                output.as_mut_ptr(),
+                len(output)?,
+            )
+        };
+        if len < 0 {
+            let error_str = error_code_to_str(len);
+            warn!("encode failed with error code {len}: {error_str}");
+            return Err(EncodeError::new("encode failed", len).into());
+        }
+        Ok(len as usize)
+    }
+
+    pub(crate) fn set(&mut self, request: c_int, value: c_int) -> Result<()> {
+        let result = unsafe { opusic_sys::opus_encoder_ctl(self.ptr, request, value) };
+
+        if result != opusic_sys::OPUS_OK {
+            let error_str = error_code_to_str(result);
+            error!("encoder request {request} failed with error code {result}: {error_str}");
+            return Err(EncodeError::new("error configuring encoder", result).into());
+        }
+        Ok(())
+    }
+
+    /// Number of samples per channel of delay added by the encoder.
+    pub(crate) fn lookahead(&mut self) -> Result<usize> {
+        let mut lookahead: opusic_sys::opus_int32 = 0;
+        let result = unsafe {
+            opusic_sys::opus_encoder_ctl(
+                self.ptr,
+                opusic_sys::OPUS_GET_LOOKAHEAD_REQUEST,
+                &mut lookahead as *mut opusic_sys::opus_int32,
+            )
+        };
+
+        if result != opusic_sys::OPUS_OK {
+            let error_str = error_code_to_str(result);
+            error!("getting lookahead failed with error code {result}: {error_str}");
+            return Err(EncodeError::new("error getting encoder lookahead", result).into());
+        }
+        Ok(lookahead.max(0) as usize)
+    }
+
+    pub(crate) fn reset(&mut self) {
+        let result =
+            unsafe { opusic_sys::opus_encoder_ctl(self.ptr, opusic_sys::OPUS_RESET_STATE) };
+
+        if result != opusic_sys::OPUS_OK {
+            let error_str = error_code_to_str(result);
+            warn!("reset failed with error code {result}: {error_str}");
+        }
+    }
+}
+
+/// Intended use of the encoder.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Application {
+    /// Favor speech intelligibility.
+    Voip,
+    /// Favor faithfulness to the input.
+    #[default]
+    Audio,
+    /// Lowest possible latency. Disables the speech-optimized mode.
+    RestrictedLowDelay,
+}
+
+/// Target bitrate.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Bitrate {
+    /// Let the encoder choose based on the sample rate and channel count.
+    #[default]
+    Auto,
+    /// Use as many bits as possible.
+    Max,
+    /// Target bitrate in bits per second.
+    Bits(u32),
+}
+
+/// Bitrate control mode.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum BitrateMode {
+    /// Unconstrained variable bitrate.
+    #[default]
+    Vbr,
+    /// Variable bitrate constrained to the bitrate of a constant bitrate stream over time.
+    ConstrainedVbr,
+    /// Constant bitrate.
+    Cbr,
+}
+
+/// Duration of each encoded packet.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FrameDuration {
+    /// 2.5 ms
+    Ms2_5,
+    /// 5 ms
+    Ms5,
+    /// 10 ms
+    Ms10,
+    /// 20 ms
+    #[default]
+    Ms20,
+    /// 40 ms
+    Ms40,
+    /// 60 ms
+    Ms60,
+    /// 80 ms
+    Ms80,
+    /// 100 ms
+    Ms100,
+    /// 120 ms
+    Ms120,
+}
+
+impl FrameDuration {
+    /// Number of samples per channel in a frame at the given sample rate.
+    fn samples(self, sample_rate: u32) -> usize {
+        // In units of 2.5 ms
+        let units = match self {
+            Self::Ms2_5 => 1,
+            Self::Ms5 => 2,
+            Self::Ms10 => 4,
+            Self::Ms20 => 8,
+            Self::Ms40 => 16,
+            Self::Ms60 => 24,
+            Self::Ms80 => 32,
+            Self::Ms100 => 40,
+            Self::Ms120 => 48,
+        };
+        sample_rate as usize / 400 * units
+    }
+}
+
+/// Hint for the type of signal being encoded.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Signal {
+    /// Let the encoder detect the signal type.
+    #[default]
+    Auto,
+    /// Bias towards speech-optimized modes.
+    Voice,
+    /// Bias towards music-optimized modes.
+    Music,
+}
+
+/// Options for [`OpusEncoder`].
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug)]
+pub struct OpusEncoderOptions {
+    /// Intended use of the encoder. This can't be changed after the encoder is created.
+    pub application: Application,
+    /// Target bitrate.
+    pub bitrate: Bitrate,
+    /// Bitrate control mode.
+    pub bitrate_mode: BitrateMode,
+    /// Computational complexity from 0 to 10. Higher values give better quality at the cost of
+    /// speed.
+    pub complexity: u8,
+    /// Duration of each encoded packet.
+    pub frame_duration: FrameDuration,
+    /// Hint for the type of signal being encoded.
+    pub signal: Signal,
+    /// Include in-band forward error correction (FEC) data so that decoders can recover lost
+    /// packets. This only has an effect when the expected packet loss is non-zero.
+    pub fec: bool,
+    /// Enable discontinuous transmission (DTX). Silence is encoded as packets of one or two bytes
+    /// which don't strictly need to be transmitted.
+    pub dtx: bool,
+    /// Expected packet loss percentage from 0 to 100.
+    pub expected_loss: u8,
+}
+
+impl Default for OpusEncoderOptions {
+    fn default() -> Self {
+        Self {
+            application: Application::default(),
+            bitrate: Bitrate::default(),
+            bitrate_mode: BitrateMode::default(),
+            complexity: 10,
+            frame_duration: FrameDuration::default(),
+            signal: Signal::default(),
+            fec: false,
+            dtx: false,
+            expected_loss: 0,
+        }
+    }
+}
+
+impl OpusEncoderOptions {
+    /// Set the intended use of the encoder.
+    pub fn application(mut self, application: Application) -> Self {
+        self.application = application;
+        self
+    }
+
+    /// Set the target bitrate.
+    pub fn bitrate(mut self, bitrate: Bitrate) -> Self {
+        self.bitrate = bitrate;
+        self
+    }
+
+    /// Set the bitrate control mode.
+    pub fn bitrate_mode(mut self, bitrate_mode: BitrateMode) -> Self {
+        self.bitrate_mode = bitrate_mode;
+        self
+    }
+
+    /// Set the computational complexity.
+    pub fn complexity(mut self, complexity: u8) -> Self {
+        self.complexity = complexity;
+        self
+    }
+
+    /// Set the duration of each encoded packet.
+    pub fn frame_duration(mut self, frame_duration: FrameDuration) -> Self {
+        self.frame_duration = frame_duration;
+        self
+    }
+
+    /// Set the signal type hint.
+    pub fn signal(mut self, signal: Signal) -> Self {
+        self.signal = signal;
+        self
+    }
+
+    /// Enable or disable in-band forward error correction.
+    pub fn fec(mut self, fec: bool) -> Self {
+        self.fec = fec;
+        self
+    }
+
+    /// Enable or disable discontinuous transmission.
+    pub fn dtx(mut self, dtx: bool) -> Self {
+        self.dtx = dtx;
+        self
+    }
+
+    /// Set the expected packet loss percentage.
+    pub fn expected_loss(mut self, expected_loss: u8) -> Self {
+        self.expected_loss = expected_loss;
+        self
+    }
+}
+
+/// Encodes mono or stereo audio into Opus packets using libopus.
+///
+/// Packet timestamps and durations are in 48 kHz units and start at zero, including the pre-skip,
+/// which matches the packets produced by Symphonia's Ogg demuxer. The final packet is trimmed so
+/// that decoding the stream and discarding [`pre_skip`](Self::pre_skip) samples yields exactly
+/// the input audio.
+pub struct OpusEncoder {
+    encoder: Encoder,
+    sample_rate: u32,
+    channels: Channels,
+    num_channels: usize,
+    /// Samples per channel in each packet.
+    frame_size: usize,
+    /// Encoder delay in samples per channel.
+    lookahead: usize,
+    /// Interleaved input that doesn't fill a full frame yet.
+    pending: Vec<f32>,
+    pcm: Vec<f32>,
+    data: Vec<u8>,
+    /// Samples per channel received so far.
+    samples_in: u64,
+    /// Samples per channel encoded so far, including padding.
+    samples_encoded: u64,
+}
+
+impl fmt::Debug for OpusEncoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpusEncoder")
+            .field("encoder", &self.encoder)
+            .field("sample_rate", &self.sample_rate)
+            .field("channels", &self.channels)
+            .field("num_channels", &self.num_channels)
+            .field("frame_size", &self.frame_size)
+            .field("lookahead", &self.lookahead)
+            .field("pending", &"<pending>")
+            .field("pcm", &"<pcm>")
+            .field("data", &"<data>")
+            .field("samples_in", &self.samples_in)
+            .field("samples_encoded", &self.samples_encoded)
+            .finish()
+    }
+}
+
+impl OpusEncoder {
+    /// Creates an encoder for audio with the given sample rate and channels. The sample rate must
+    /// be 8, 12, 16, 24 or 48 kHz.
+    pub fn try_new(
+        sample_rate: u32,
+        channels: Channels,
+        opts: &OpusEncoderOptions,
+    ) -> Result<Self> {
+        if ![8000, 12000, 16000, 24000, 48000].contains(&sample_rate) {
+            return unsupported_error("opus: unsupported sample rate");
+        }
+        let num_channels = channels.count();
+        if !(1..=2).contains(&num_channels) {
+            return unsupported_error("opus: the encoder only supports mono and stereo audio");
+        }
+        if opts.complexity > 10 {
+            return unsupported_error("opus: complexity must be between 0 and 10");
+        }
+        if opts.expected_loss > 100 {
+            return unsupported_error("opus: expected loss must be between 0 and 100");
+        }
+
+        let application = match opts.application {
+            Application::Voip => opusic_sys::OPUS_APPLICATION_VOIP,
+            Application::Audio => opusic_sys::OPUS_APPLICATION_AUDIO,
+            Application::RestrictedLowDelay => opusic_sys::OPUS_APPLICATION_RESTRICTED_LOWDELAY,
+        };
+        let mut encoder = Encoder::new(sample_rate, num_channels as u32, application)?;
+
+        let bitrate = match opts.bitrate {
+            Bitrate::Auto => opusic_sys::OPUS_AUTO,
+            Bitrate::Max => opusic_sys::OPUS_BITRATE_MAX,
+            Bitrate::Bits(bits) => bits.min(c_int::MAX as u32) as c_int,
+        };
+        encoder.set(opusic_sys::OPUS_SET_BITRATE_REQUEST, bitrate)?;
+        encoder.set(
+            opusic_sys::OPUS_SET_VBR_REQUEST,
+            (opts.bitrate_mode != BitrateMode::Cbr) as c_int,
+        )?;
+        encoder.set(
+            opusic_sys::OPUS_SET_VBR_CONSTRAINT_REQUEST,
+            (opts.bitrate_mode == BitrateMode::ConstrainedVbr) as c_int,
+        )?;
+        encoder.set(
+            opusic_sys::OPUS_SET_COMPLEXITY_REQUEST,
+            opts.complexity as c_int,
+        )?;
+        let signal = match opts.signal {
+            Signal::Auto => opusic_sys::OPUS_AUTO,
+            Signal::Voice => opusic_sys::OPUS_SIGNAL_VOICE,
+            Signal::Music => opusic_sys::OPUS_SIGNAL_MUSIC,
+        };
+        encoder.set(opusic_sys::OPUS_SET_SIGNAL_REQUEST, signal)?;
+        encoder.set(opusic_sys::OPUS_SET_INBAND_FEC_REQUEST, opts.fec as c_int)?;
+        encoder.set(opusic_sys::OPUS_SET_DTX_REQUEST, opts.dtx as c_int)?;
+        encoder.set(
+            opusic_sys::OPUS_SET_PACKET_LOSS_PERC_REQUEST,
+            opts.expected_loss as c_int,
+        )?;
+        let lookahead = encoder.lookahead()?;
+
+        Ok(Self {
+            encoder,
+            sample_rate,
+            channels,
+            num_channels,
+            frame_size: opts.frame_duration.samples(sample_rate),
+            lookahead,
+            pending: Vec::new(),
+            pcm: Vec::new(),
+            data: vec![0; MAX_PACKET_SIZE],
+            samples_in: 0,
+            samples_encoded: 0,
+        })
+    }
+
+    /// Number of samples per channel (at the input sample rate) of delay added by the encoder.
+    pub fn lookahead(&self) -> usize {
+        self.lookahead
+    }
+
+    /// Number of samples (at 48 kHz) to discard from the start of the decoded stream.
+    pub fn pre_skip(&self) -> u16 {
+        (self.lookahead * self.scale() as usize).min(u16::MAX as usize) as u16
+    }
+
+    /// Identification header describing the encoded stream.
+    pub fn head(&self) -> OpusHead {
+        OpusHead {
+            layout: OpusHeadLayout::Ogg,
+            version: 1,
+            channels: self.num_channels as u8,
+            pre_skip: self.pre_skip(),
+            input_sample_rate: self.sample_rate,
+            output_gain: 0,
+            channel_mapping: ChannelMapping::default_for(self.num_channels),
+        }
+    }
+
+    /// Encodes the audio in `buf` and returns any packets that were completed. Audio that doesn't
+    /// fill a whole packet is buffered until the next call or [`flush`](Self::flush).
+    pub fn encode(&mut self, buf: GenericAudioBufferRef<'_>) -> Result<Vec<Packet>> {
+        let spec = buf.spec();
+        if spec.rate() != self.sample_rate || spec.channels().count() != self.num_channels {
+            return unsupported_error("opus: audio does not match the encoder's specification");
+        }
+
+        buf.copy_to_vec_interleaved(&mut self.pcm);
+        self.pending.extend_from_slice(&self.pcm);
+        self.samples_in += buf.frames() as u64;
+
+        let frame_len = self.frame_size * self.num_channels;
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        while self.pending.len() - offset >= frame_len {
+            let packet = self.encode_frame(offset, 0)?;
+            packets.push(packet);
+            offset += frame_len;
+        }
+        self.pending.drain(..offset);
+        Ok(packets)
+    }
+
+    /// Encodes any buffered audio, padding it with silence to flush the encoder's lookahead, and
+    /// returns the remaining packets. The encoder is reset afterwards so it can be used for a new
+    /// stream.
+    pub fn flush(&mut self) -> Result<Vec<Packet>> {
+        let mut packets = Vec::new();
+        if self.samples_in > 0 {
+            let frame_len = self.frame_size * self.num_channels;
+            let end = self.samples_in + self.lookahead as u64;
+            while self.samples_encoded < end {
+                self.pending.resize(frame_len, 0.0);
+                let padding = (self.samples_encoded + self.frame_size as u64).saturating_sub(end);
+                let packet = self.encode_frame(0, padding)?;
+                packets.push(packet);
+                self.pending.clear();
+            }
+        }
+
+        self.encoder.reset();
+        self.pending.clear();
+        self.samples_in = 0;
+        self.samples_encoded = 0;
+        Ok(packets)
+    }
+
+    /// Encodes a single frame from the pending input starting at `offset`, with `padding` samples
+    /// per channel trimmed from the end.
+    fn encode_frame(&mut self, offset: usize, padding: u64) -> Result<Packet> {
+        let frame_len = self.frame_size * self.num_channels;
+        let input = &self.pending[offset..offset + frame_len];
+        let len = self.encoder.encode(input, &mut self.data)?;
+
+        let scale = self.scale();
+        let pts = self.samples_encoded * scale;
+        let dur = self.frame_size as u64 * scale;
+        let trim_end = padding * scale;
+        self.samples_encoded += self.frame_size as u64;
+
+        let mut packet = Packet::new(
+            0,
+            Timestamp::new(pts as i64),
+            Duration::new(dur - trim_end),
+            &self.data[..len],
+        );
+        packet.trim_end = Duration::new(trim_end);
+        Ok(packet)
+    }
+
+    /// Ratio between 48 kHz timestamps and input samples.
+    fn scale(&self) -> u64 {
+        DEFAULT_SAMPLE_RATE as u64 / self.sample_rate as u64
+    }
+}
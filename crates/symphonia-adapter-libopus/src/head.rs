@@ -0,0 +1,335 @@
+use symphonia_core::audio::{Channels, Position};
+use symphonia_core::errors::{Result, decode_error};
+use symphonia_core::io::{BufReader, ReadBytes};
+
+const OPUS_MAGIC: &[u8; 8] = b"OpusHead";
+const TAGS_MAGIC: &[u8; 8] = b"OpusTags";
+
+/// Layout of the identification header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpusHeadLayout {
+    /// The `OpusHead` packet used by Ogg and Matroska. Fields are little-endian.
+    ///
+    /// See <https://www.rfc-editor.org/rfc/rfc7845#section-5.1>.
+    Ogg,
+    /// The `dOps` box used by MP4. Fields are big-endian and there is no magic signature.
+    ///
+    /// See <https://opus-codec.org/docs/opus_in_isobmff.html#4.3.2>.
+    Mp4,
+}
+
+/// Opus identification header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpusHead {
+    /// Layout the header was parsed from.
+    pub layout: OpusHeadLayout,
+    /// Header version. This is 1 for `OpusHead` and 0 for `dOps`.
+    pub version: u8,
+    /// Number of output channels.
+    pub channels: u8,
+    /// Number of samples (at 48 kHz) to discard from the start of the stream.
+    pub pre_skip: u16,
+    /// Sample rate of the original input. This is informational only.
+    pub input_sample_rate: u32,
+    /// Gain to apply to the decoded output in Q7.8 dB.
+    pub output_gain: i16,
+    /// Channel mapping family and table.
+    pub channel_mapping: ChannelMapping,
+}
+
+impl OpusHead {
+    /// Parses an identification header. Both the `OpusHead` layout and the `dOps` layout (with or
+    /// without a leading `OpusHead` signature) are accepted. Use [`read_layout`](Self::read_layout)
+    /// if the container is known.
+    pub fn read(buf: &[u8]) -> Result<Self> {
+        let layout = match buf.strip_prefix(OPUS_MAGIC) {
+            // Symphonia's MP4 demuxer prefixes the dOps box with the OpusHead signature. The
+            // layouts only differ in byte order, so dOps is only assumed if the version and the
+            // length match it exactly.
+            Some(body) if is_dops(body) => OpusHeadLayout::Mp4,
+            Some(_) => OpusHeadLayout::Ogg,
+            None if is_dops(buf) => OpusHeadLayout::Mp4,
+            None => return decode_error("opus: unrecognized identification header"),
+        };
+        Self::read_layout(buf, layout)
+    }
+
+    /// Parses an identification header in the given layout. The `OpusHead` signature is required
+    /// for the `OpusHead` layout and optional for the `dOps` layout.
+    pub fn read_layout(buf: &[u8], layout: OpusHeadLayout) -> Result<Self> {
+        let buf = match (buf.strip_prefix(OPUS_MAGIC), layout) {
+            (Some(body), _) => body,
+            (None, OpusHeadLayout::Mp4) => buf,
+            (None, OpusHeadLayout::Ogg) => return decode_error("opus: missing OpusHead signature"),
+        };
+        let mut reader = BufReader::new(buf);
+        let be = layout == OpusHeadLayout::Mp4;
+
+        let version = reader.read_byte()?;
+        let channels = reader.read_byte()?;
+        if channels == 0 {
+            return decode_error("opus: invalid channel count");
+        }
+
+        let pre_skip = if be {
+            reader.read_be_u16()?
+        } else {
+            reader.read_u16()?
+        };
+        let input_sample_rate = if be {
+            reader.read_be_u32()?
+        } else {
+            reader.read_u32()?
+        };
+        let output_gain = if be {
+            reader.read_be_i16()?
+        } else {
+            reader.read_i16()?
+        };
+
+        let family = reader.read_byte()?;
+        let channel_mapping = if family == 0 {
+            if channels > 2 {
+                return decode_error("opus: mapping family 0 only supports up to 2 channels");
+            }
+            ChannelMapping::default_for(channels as usize)
+        } else {
+            let streams = reader.read_byte()?;
+            let coupled_streams = reader.read_byte()?;
+            if streams == 0 || coupled_streams > streams {
+                return decode_error("opus: invalid stream count");
+            }
+            let mut mapping = vec![0; channels as usize];
+            reader.read_buf_exact(&mut mapping)?;
+
+            let decoded_channels = streams as usize + coupled_streams as usize;
+            if mapping
+                .iter()
+                .any(|idx| *idx != 255 && *idx as usize >= decoded_channels)
+            {
+                return decode_error("opus: invalid channel mapping");
+            }
+            ChannelMapping {
+                family,
+                streams,
+                coupled_streams,
+                mapping,
+            }
+        };
+
+        Ok(Self {
+            layout,
+            version,
+            channels,
+            pre_skip,
+            input_sample_rate,
+            output_gain,
+            channel_mapping,
+        })
+    }
+
+    /// Serializes the header in the given layout. The version is set to the one defined for the
+    /// layout.
+    pub fn to_bytes(&self, layout: OpusHeadLayout) -> Vec<u8> {
+        let mut buf =
+            Vec::with_capacity(OPUS_MAGIC.len() + 21 + self.channel_mapping.mapping.len());
+        match layout {
+            OpusHeadLayout::Ogg => {
+                buf.extend_from_slice(OPUS_MAGIC);
+                buf.push(1);
+            }
+            OpusHeadLayout::Mp4 => buf.push(0),
+        }
+        buf.push(self.channels);
+        if layout == OpusHeadLayout::Mp4 {
+            buf.extend_from_slice(&self.pre_skip.to_be_bytes());
+            buf.extend_from_slice(&self.input_sample_rate.to_be_bytes());
+            buf.extend_from_slice(&self.output_gain.to_be_bytes());
+        } else {
+            buf.extend_from_slice(&self.pre_skip.to_le_bytes());
+            buf.extend_from_slice(&self.input_sample_rate.to_le_bytes());
+            buf.extend_from_slice(&self.output_gain.to_le_bytes());
+        }
+
+        let mapping = &self.channel_mapping;
+        buf.push(mapping.family);
+        if mapping.family != 0 {
+            buf.push(mapping.streams);
+            buf.push(mapping.coupled_streams);
+            buf.extend_from_slice(&mapping.mapping);
+        }
+        buf
+    }
+
+    /// Creates a header for a mono or stereo stream with no pre-skip or gain.
+    pub(crate) fn default_for(num_channels: usize) -> Self {
+        Self {
+            layout: OpusHeadLayout::Ogg,
+            version: 1,
+            channels: num_channels as u8,
+            pre_skip: 0,
+            input_sample_rate: 0,
+            output_gain: 0,
+            channel_mapping: ChannelMapping::default_for(num_channels),
+        }
+    }
+
+    /// Returns `true` if the version is one this crate was written against. `OpusHead` versions
+    /// only change the major version in the upper 4 bits for incompatible changes.
+    pub fn is_version_supported(&self) -> bool {
+        match self.layout {
+            OpusHeadLayout::Ogg => self.version & 0xf0 == 0,
+            OpusHeadLayout::Mp4 => self.version == 0,
+        }
+    }
+}
+
+/// Checks if the body has the version and exact length of a `dOps` box.
+fn is_dops(body: &[u8]) -> bool {
+    let (Some(&0), Some(&channels), Some(&family)) = (body.first(), body.get(1), body.get(10))
+    else {
+        return false;
+    };
+    let len = if family == 0 {
+        11
+    } else {
+        13 + channels as usize
+    };
+    body.len() == len
+}
+
+/// Opus comment header.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc7845#section-5.2>.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpusTags {
+    /// Name of the encoder that produced the stream.
+    pub vendor: String,
+    /// Comments as `(field, value)` pairs, such as `("TITLE", "...")`.
+    pub comments: Vec<(String, String)>,
+}
+
+impl Default for OpusTags {
+    fn default() -> Self {
+        let vendor = unsafe { std::ffi::CStr::from_ptr(opusic_sys::opus_get_version_string()) };
+        Self {
+            vendor: vendor.to_string_lossy().into_owned(),
+            comments: Vec::new(),
+        }
+    }
+}
+
+impl OpusTags {
+    /// Serializes the comment header.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(TAGS_MAGIC);
+        buf.extend_from_slice(&(self.vendor.len() as u32).to_le_bytes());
+        buf.extend_from_slice(self.vendor.as_bytes());
+        buf.extend_from_slice(&(self.comments.len() as u32).to_le_bytes());
+        for (field, value) in &self.comments {
+            buf.extend_from_slice(&((field.len() + value.len() + 1) as u32).to_le_bytes());
+            buf.extend_from_slice(field.as_bytes());
+            buf.push(b'=');
+            buf.extend_from_slice(value.as_bytes());
+        }
+        buf
+    }
+}
+
+/// Channel mapping parameters from the identification header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelMapping {
+    /// Channel mapping family.
+    ///
+    /// See <https://www.rfc-editor.org/rfc/rfc7845#section-5.1.1>.
+    pub family: u8,
+    /// Number of Opus streams in each packet.
+    pub streams: u8,
+    /// Number of streams that are coupled stereo pairs.
+    pub coupled_streams: u8,
+    /// Maps each output channel to a decoded stream channel. An index of 255 indicates a silent
+    /// channel.
+    pub mapping: Vec<u8>,
+}
+
+impl ChannelMapping {
+    /// Mapping family 0 - a single mono or stereo stream.
+    pub(crate) fn default_for(num_channels: usize) -> Self {
+        Self {
+            family: 0,
+            streams: 1,
+            coupled_streams: (num_channels > 1) as u8,
+            mapping: (0..num_channels as u8).collect(),
+        }
+    }
+
+    /// Speaker positions in Vorbis channel order. Only mapping families 0 and 1 define speaker
+    /// positions.
+    fn positions(&self) -> Option<&'static [Position]> {
+        match self.family {
+            0 | 1 => vorbis_positions(self.mapping.len()),
+            _ => None,
+        }
+    }
+
+    /// Channel layout of the decoded audio.
+    pub fn channels(&self) -> Channels {
+        match self.positions() {
+            Some(positions) => Channels::Positioned(
+                positions
+                    .iter()
+                    .fold(Position::empty(), |acc, pos| acc.union(*pos)),
+            ),
+            None => Channels::Discrete(self.mapping.len() as u16),
+        }
+    }
+
+    /// Reorders the mapping table from the Vorbis channel order into Symphonia's canonical channel
+    /// order so that the decoder output can be copied directly into an
+    /// [`AudioBuffer`](symphonia_core::audio::AudioBuffer).
+    pub(crate) fn to_canonical_order(&self) -> Self {
+        let Some(positions) = self.positions() else {
+            return self.clone();
+        };
+        let channels = self.channels();
+        let mut mapping = self.mapping.clone();
+        for (vorbis_idx, pos) in positions.iter().enumerate() {
+            if let Some(canonical_idx) = channels.get_canonical_index_for_positioned_channel(*pos) {
+                mapping[canonical_idx] = self.mapping[vorbis_idx];
+            }
+        }
+        Self {
+            mapping,
+            ..self.clone()
+        }
+    }
+}
+
+/// Speaker positions for mapping families 0 and 1, in Vorbis channel order.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc7845#section-5.1.1.2>.
+fn vorbis_positions(num_channels: usize) -> Option<&'static [Position]> {
+    const FL: Position = Position::FRONT_LEFT;
+    const FR: Position = Position::FRONT_RIGHT;
+    const FC: Position = Position::FRONT_CENTER;
+    const LFE: Position = Position::LFE1;
+    const RL: Position = Position::REAR_LEFT;
+    const RR: Position = Position::REAR_RIGHT;
+    const RC: Position = Position::REAR_CENTER;
+    const SL: Position = Position::SIDE_LEFT;
+    const SR: Position = Position::SIDE_RIGHT;
+
+    let positions: &'static [Position] = match num_channels {
+        1 => &[FC],
+        2 => &[FL, FR],
+        3 => &[FL, FC, FR],
+        4 => &[FL, FR, RL, RR],
+        5 => &[FL, FC, FR, RL, RR],
+        6 => &[FL, FC, FR, RL, RR, LFE],
+        7 => &[FL, FC, FR, SL, SR, RC, LFE],
+        8 => &[FL, FC, FR, SL, SR, RL, RR, LFE],
+        _ => return None,
+    };
+    Some(positions)
+}
@@ -5,9 +5,9 @@
 
 use std::fmt;
 
+use log::{debug, warn};
 use symphonia_core::audio::{
     AsGenericAudioBufferRef, AudioBuffer, AudioMut, AudioSpec, Channels, GenericAudioBufferRef,
-    layouts,
 };
 use symphonia_core::codecs::CodecInfo;
 use symphonia_core::codecs::audio::well_known::CODEC_ID_OPUS;
@@ -15,32 +15,61 @@ use symphonia_core::codecs::audio::{
     AudioCodecParameters, AudioDecoder, AudioDecoderOptions, FinalizeResult,
 };
 use symphonia_core::codecs::registry::{RegisterableAudioDecoder, SupportedAudioCodec};
-use symphonia_core::errors::{Result, unsupported_error};
-use symphonia_core::io::{BufReader, ReadBytes};
+use symphonia_core::errors::{Result, decode_error, unsupported_error};
+use symphonia_core::packet::PacketRef;
 use symphonia_core::support_audio_codec;
 
 use crate::decoder::Decoder;
+pub use crate::encoder::{
+    Application, Bitrate, BitrateMode, EncodeError, FrameDuration, OpusEncoder, OpusEncoderOptions,
+    Signal,
+};
+pub use crate::head::{ChannelMapping, OpusHead, OpusHeadLayout, OpusTags};
+pub use crate::ogg::OggOpusWriter;
+use crate::packet::packet_samples;
+pub use crate::packet::{Bandwidth, OpusMode, OpusPacket};
 
 mod decoder;
+mod encoder;
+mod head;
+mod ogg;
+mod packet;
 
 /// Maximum sampling rate is 48 kHz for normal opus, and 96 kHz for Opus HD in the 1.6 spec.
 const MAX_SAMPLE_RATE: usize = 48000;
 const DEFAULT_SAMPLE_RATE: usize = 48000;
-/// Assuming 48 kHz sample rate with the default 20 ms frames.
-const DEFAULT_SAMPLES_PER_CHANNEL: usize = DEFAULT_SAMPLE_RATE * 20 / 1000;
 /// Opus maximum frame size is 60 ms, with worst case being 120 ms when combining frames per packet.
 const MAX_SAMPLES_PER_CHANNEL: usize = MAX_SAMPLE_RATE * 120 / 1000;
+/// Timestamp gaps longer than this are treated as discontinuities rather than packet loss.
+const MAX_CONCEALED_MS: usize = 1000;
+/// Audio to discard after a reset while the decoder converges, as recommended by RFC 7845.
+const SEEK_PRE_ROLL: u16 = 3840;
 
 /// Symphonia-compatible wrapper for the libopus decoder.
 pub struct OpusDecoder {
     params: AudioCodecParameters,
     decoder: Decoder,
     buf: AudioBuffer<f32>,
-    pcm: [f32; MAX_SAMPLES_PER_CHANNEL * 2],
-    samples_per_channel: usize,
+    pcm: Vec<f32>,
     sample_rate: u32,
+    channels: Channels,
     num_channels: usize,
+    /// Number of streams in each packet.
+    streams: u8,
+    /// Samples per channel (at the output sample rate) left to discard from the start of the
+    /// stream.
     pre_skip: usize,
+    fec: bool,
+    /// Samples per channel (at the output sample rate) left to discard after a reset.
+    pre_roll: usize,
+    /// Duration of the last decoded packet in samples per channel, used to size concealment for
+    /// lost packets.
+    last_packet_duration: usize,
+    /// Expected timestamp of the next packet, used to detect lost packets.
+    next_pts: Option<i64>,
+    /// Duration of the last packet as (timestamp units, samples per channel), used to convert
+    /// timestamp gaps into samples.
+    last_packet_timing: Option<(u64, usize)>,
 }
 
 impl fmt::Debug for OpusDecoder {
@@ -50,40 +79,121 @@ impl fmt::Debug for OpusDecoder {
             .field("decoder", &self.decoder)
             .field("buf", &"<buf>")
             .field("pcm", &"<pcm>")
-            .field("samples_per_channel", &self.samples_per_channel)
             .field("sample_rate", &self.sample_rate)
+            .field("channels", &self.channels)
             .field("num_channels", &self.num_channels)
+            .field("streams", &self.streams)
+            .field("pre_skip", &self.pre_skip)
+            .field("fec", &self.fec)
+            .field("pre_roll", &self.pre_roll)
+            .field("last_packet_duration", &self.last_packet_duration)
+            .field("next_pts", &self.next_pts)
+            .field("last_packet_timing", &self.last_packet_timing)
             .finish()
     }
 }
 
-// This should probably be handled in the Ogg demuxer, but we'll include it here for now.
-fn parse_pre_skip(buf: &[u8]) -> Result<usize> {
-    // See https://wiki.xiph.org/OggOpus
+/// Additional options for [`OpusDecoder`].
+#[non_exhaustive]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct OpusDecoderOptions {
+    /// Reject identification headers with an unsupported version, a channel count that disagrees
+    /// with the codec parameters, or missing fields. If disabled, invalid mono and stereo headers
+    /// are ignored and the stream is decoded without pre-skip.
+    pub strict: bool,
+    /// Gain to apply to the decoded output.
+    pub output_gain: OutputGain,
+    /// Recover lost packets from the in-band forward error correction (FEC) data in the packet
+    /// following the loss. Only packets from encoders with FEC enabled contain this data,
+    /// otherwise the loss is concealed as usual.
+    pub fec: bool,
+}
 
-    let mut reader = BufReader::new(buf);
+impl OpusDecoderOptions {
+    /// Enable or disable strict header validation.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
 
-    // Header - "OpusHead"
-    let mut header = [0; 8];
-    reader.read_buf_exact(&mut header)?;
+    /// Set the gain to apply to the decoded output.
+    pub fn output_gain(mut self, output_gain: OutputGain) -> Self {
+        self.output_gain = output_gain;
+        self
+    }
 
-    // Version - 1 is the only valid version currently
-    reader.read_byte()?;
+    /// Enable or disable in-band forward error correction.
+    pub fn fec(mut self, fec: bool) -> Self {
+        self.fec = fec;
+        self
+    }
+}
 
-    // Number of channels (same as what we get from the CodecParameters)
-    reader.read_byte()?;
+/// Gain to apply to the decoded output.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum OutputGain {
+    /// Apply the output gain from the identification header, as required by RFC 7845.
+    #[default]
+    Header,
+    /// Don't apply any gain. Useful if the output is normalized separately.
+    Disabled,
+    /// Apply a custom gain in Q7.8 dB instead of the gain from the identification header.
+    Custom(i16),
+}
 
-    // Pre-skip - number of samples (at 48 kHz) to discard from the start of the stream
-    let pre_skip = reader.read_u16()?;
+/// Converts pre-skip or pre-roll from 48 kHz to the output sample rate. This is exact for every
+/// sample rate libopus can decode to, but is rounded up otherwise so that no pre-skip audio leaks
+/// into the output.
+fn scale_pre_skip(pre_skip: u16, sample_rate: u32) -> usize {
+    (pre_skip as u64 * sample_rate as u64).div_ceil(DEFAULT_SAMPLE_RATE as u64) as usize
+}
 
-    Ok(pre_skip as usize)
+fn read_head(
+    params: &AudioCodecParameters,
+    num_channels: usize,
+    opus_opts: &OpusDecoderOptions,
+) -> Result<OpusHead> {
+    let head = match &params.extra_data {
+        Some(extra_data) => OpusHead::read(extra_data),
+        None => decode_error("opus: missing identification header"),
+    };
+    let head = match head {
+        Ok(head) => head,
+        Err(e) if opus_opts.strict || num_channels > 2 => return Err(e),
+        Err(e) => {
+            // Mono and stereo streams can still be decoded without a valid header
+            warn!("opus: ignoring invalid identification header: {e}");
+            return Ok(OpusHead::default_for(num_channels));
+        }
+    };
+
+    if opus_opts.strict {
+        if !head.is_version_supported() {
+            return unsupported_error("opus: unsupported identification header version");
+        }
+        if head.channels as usize != num_channels {
+            return decode_error("opus: channel count does not match the codec parameters");
+        }
+    } else if head.channels as usize != num_channels {
+        warn!(
+            "opus: identification header has {} channels but the codec parameters have {}",
+            head.channels, num_channels
+        );
+    }
+    Ok(head)
 }
 
 impl OpusDecoder {
-    fn try_new(params: &AudioCodecParameters, _opts: &AudioDecoderOptions) -> Result<Self>
-    where
-        Self: Sized,
-    {
+    fn try_new(params: &AudioCodecParameters, opts: &AudioDecoderOptions) -> Result<Self> {
+        Self::try_new_with_options(params, opts, &OpusDecoderOptions::default())
+    }
+
+    /// Creates a decoder with additional Opus-specific options.
+    pub fn try_new_with_options(
+        params: &AudioCodecParameters,
+        _opts: &AudioDecoderOptions,
+        opus_opts: &OpusDecoderOptions,
+    ) -> Result<Self> {
         let num_channels = if let Some(channels) = &params.channels {
             channels.count()
         } else {
@@ -95,28 +205,134 @@ impl OpusDecoder {
             return unsupported_error("opus: sample rate required");
         };
 
-        if !(1..=2).contains(&num_channels) {
+        if num_channels == 0 || num_channels > 255 {
             return unsupported_error("opus: unsupported number of channels");
         }
 
-        let pre_skip = if let Some(extra_data) = &params.extra_data {
-            parse_pre_skip(extra_data).unwrap_or_default()
-        } else {
-            0
+        let head = read_head(params, num_channels, opus_opts)?;
+        // The header is authoritative since the channel mapping table depends on it
+        let num_channels = head.channels as usize;
+        let mapping = head.channel_mapping.to_canonical_order();
+        let channels = mapping.channels();
+
+        let mut decoder = Decoder::new(sample_rate, &mapping)?;
+        let gain = match opus_opts.output_gain {
+            OutputGain::Header => head.output_gain,
+            OutputGain::Disabled => 0,
+            OutputGain::Custom(gain) => gain,
         };
+        if gain != 0 {
+            decoder.set_gain(gain)?;
+        }
 
-        Ok(Self {
-            params: params.to_owned(),
-            decoder: Decoder::new(sample_rate, num_channels as u32)?,
+        // Report what is actually decoded rather than what the container claims
+        let mut params = params.to_owned();
+        params
+            .with_sample_rate(sample_rate)
+            .with_channels(channels.clone());
 
-            buf: audio_buffer(sample_rate, DEFAULT_SAMPLES_PER_CHANNEL, num_channels),
-            pcm: [0.0; _],
-            samples_per_channel: DEFAULT_SAMPLES_PER_CHANNEL,
+        Ok(Self {
+            params,
+            decoder,
+            buf: audio_buffer(sample_rate, MAX_SAMPLES_PER_CHANNEL, channels.clone()),
+            pcm: vec![0.0; MAX_SAMPLES_PER_CHANNEL * num_channels],
             sample_rate,
+            channels,
             num_channels,
-            pre_skip,
+            streams: mapping.streams,
+            pre_skip: scale_pre_skip(head.pre_skip, sample_rate),
+            fec: opus_opts.fec,
+            pre_roll: 0,
+            last_packet_duration: sample_rate as usize * 20 / 1000,
+            next_pts: None,
+            last_packet_timing: None,
         })
     }
+
+    /// Makes sure the PCM buffer can hold `samples_per_channel` samples for each channel.
+    fn reserve_pcm(&mut self, samples_per_channel: usize) {
+        let samples = samples_per_channel * self.num_channels;
+        if self.pcm.len() < samples {
+            self.pcm.resize(samples, 0.0);
+        }
+    }
+
+    /// Writes `samples_per_channel` samples of concealed audio into the PCM buffer starting at
+    /// `offset`.
+    fn conceal(&mut self, offset: usize, samples_per_channel: usize) -> Result<usize> {
+        // Each call to the decoder can conceal at most 120 ms
+        let max_chunk = self.sample_rate as usize * 120 / 1000;
+        self.reserve_pcm(offset + samples_per_channel);
+
+        let mut concealed = 0;
+        while concealed < samples_per_channel {
+            let chunk = (samples_per_channel - concealed).min(max_chunk);
+            let start = (offset + concealed) * self.num_channels;
+            let len = self.decoder.conceal(chunk, &mut self.pcm[start..])?;
+            if len == 0 {
+                break;
+            }
+            concealed += len;
+        }
+        Ok(concealed)
+    }
+
+    /// Conceals audio for any packets that are missing between the last packet and this one.
+    fn conceal_gap(&mut self, packet: &PacketRef<'_>) -> Result<usize> {
+        let (Some(next_pts), Some((dur, dur_samples))) = (self.next_pts, self.last_packet_timing)
+        else {
+            return Ok(0);
+        };
+        let gap = packet.pts.get().saturating_sub(next_pts);
+        // Trimmed packets only occur at the start and end of the stream, so a gap here is more
+        // likely to be a discontinuity.
+        if gap <= 0 || dur == 0 || packet.trim_start.get() > 0 {
+            return Ok(0);
+        }
+
+        let gap_samples = (gap as u128 * dur_samples as u128 / dur as u128) as usize;
+        // Concealment must be done in multiples of 2.5 ms
+        let frame = self.sample_rate as usize / 400;
+        let gap_samples = gap_samples / frame * frame;
+        if gap_samples == 0 {
+            return Ok(0);
+        }
+        if gap_samples > self.sample_rate as usize * MAX_CONCEALED_MS / 1000 {
+            warn!("opus: timestamp gap of {gap_samples} samples is too large to conceal");
+            return Ok(0);
+        }
+
+        if !self.fec || packet.data.is_empty() {
+            debug!("opus: concealing {gap_samples} samples of lost audio");
+            return self.conceal(0, gap_samples);
+        }
+
+        // FEC data can only recover the frame immediately before this packet, so anything before
+        // that is concealed.
+        let max_chunk = self.sample_rate as usize * 120 / 1000;
+        let fec_samples = gap_samples.min(max_chunk);
+        let concealed = self.conceal(0, gap_samples - fec_samples)?;
+
+        debug!("opus: recovering {fec_samples} samples of lost audio with FEC");
+        self.reserve_pcm(gap_samples);
+        let start = concealed * self.num_channels;
+        let recovered =
+            self.decoder
+                .decode_fec(packet.data, fec_samples, &mut self.pcm[start..])?;
+        Ok(concealed + recovered)
+    }
+
+    fn update_timing(&mut self, packet: &PacketRef<'_>, samples_per_channel: usize) {
+        let dur = packet.dur.get();
+        if dur == 0 {
+            self.next_pts = None;
+            return;
+        }
+        self.next_pts = packet.pts.get().checked_add_unsigned(dur);
+        if !packet.data.is_empty() && samples_per_channel > 0 {
+            self.last_packet_timing = Some((dur, samples_per_channel));
+        }
+    }
 }
 
 impl AudioDecoder for OpusDecoder {
@@ -128,38 +344,62 @@ impl AudioDecoder for OpusDecoder {
     }
 
     fn reset(&mut self) {
-        self.decoder.reset()
+        self.decoder.reset();
+        self.next_pts = None;
+        // The first packets after a seek decode incorrectly until the decoder converges
+        self.pre_roll = scale_pre_skip(SEEK_PRE_ROLL, self.sample_rate);
     }
 
     fn codec_params(&self) -> &AudioCodecParameters {
         &self.params
     }
 
-    fn decode_ref(
-        &mut self,
-        packet: &symphonia_core::packet::PacketRef<'_>,
-    ) -> Result<GenericAudioBufferRef<'_>> {
-        let samples_per_channel = self.decoder.decode(packet.data, &mut self.pcm)?;
+    fn decode_ref(&mut self, packet: &PacketRef<'_>) -> Result<GenericAudioBufferRef<'_>> {
+        let concealed = self.conceal_gap(packet)?;
 
-        if samples_per_channel != self.samples_per_channel {
-            self.buf = audio_buffer(self.sample_rate, samples_per_channel, self.num_channels);
-            self.samples_per_channel = samples_per_channel;
+        let decoded = if packet.data.is_empty() {
+            // An empty packet signals a lost packet, so conceal it with the duration of the last
+            // one
+            self.conceal(concealed, self.last_packet_duration)?
+        } else {
+            let samples = packet_samples(packet.data, self.streams, self.sample_rate)?;
+            self.reserve_pcm(concealed + samples);
+            let start = concealed * self.num_channels;
+            let end = start + samples * self.num_channels;
+            self.decoder
+                .decode(packet.data, &mut self.pcm[start..end])?
+        };
+        self.last_packet_duration = self.decoder.last_packet_duration()?;
+        self.update_timing(packet, decoded);
+        if self.params.frames_per_block.is_none() && !packet.data.is_empty() {
+            self.params
+                .with_frames_per_block(self.last_packet_duration as u64);
         }
 
+        let samples_per_channel = concealed + decoded;
         let samples = samples_per_channel * self.num_channels;
         let pcm = &self.pcm[..samples];
 
+        // The buffer holds a full 120 ms packet, so it only grows when concealment adds to that
+        self.buf.grow_capacity(samples_per_channel);
         self.buf.clear();
-        self.buf.render_uninit(None);
+        self.buf.render_uninit(Some(samples_per_channel));
         self.buf.copy_from_slice_interleaved(&pcm);
 
-        self.buf.trim(
-            packet.trim_start.get() as usize
-                + (self.pre_skip * self.sample_rate as usize) / DEFAULT_SAMPLE_RATE,
-            packet.trim_end.get() as usize,
-        );
-        // Pre-skip should only be used for the first packet, after that it should always be 0.
-        self.pre_skip = 0;
+        // Pre-skip can be longer than a single packet, so discard as much as this packet holds
+        // and carry the rest over to the next one.
+        let packet_trim_start = packet.trim_start.get() as usize;
+        let pre_skip = self
+            .pre_skip
+            .min(samples_per_channel.saturating_sub(packet_trim_start));
+        self.pre_skip -= pre_skip;
+        let trim_start = packet_trim_start + pre_skip;
+        // Like pre-skip, the pre-roll can span several packets. It overlaps with any trimming
+        // requested by the demuxer, so only the larger of the two is discarded.
+        let pre_roll = self.pre_roll.min(samples_per_channel);
+        self.pre_roll -= pre_roll;
+        self.buf
+            .trim(trim_start.max(pre_roll), packet.trim_end.get() as usize);
         Ok(self.buf.as_generic_audio_buffer_ref())
     }
 
@@ -188,22 +428,11 @@ impl RegisterableAudioDecoder for OpusDecoder {
     }
 }
 
-pub(crate) fn map_to_channels(num_channels: usize) -> Option<Channels> {
-    let channels = match num_channels {
-        1 => layouts::CHANNEL_LAYOUT_MONO,
-        2 => layouts::CHANNEL_LAYOUT_STEREO,
-        _ => return None,
-    };
-
-    Some(channels)
-}
-
 fn audio_buffer(
     sample_rate: u32,
     samples_per_channel: usize,
-    num_channels: usize,
+    channels: Channels,
 ) -> AudioBuffer<f32> {
-    let channels = map_to_channels(num_channels).expect("invalid channels");
     let spec = AudioSpec::new(sample_rate, channels);
     AudioBuffer::new(spec, samples_per_channel)
 }
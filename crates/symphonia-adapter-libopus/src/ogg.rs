@@ -0,0 +1,211 @@
+use std::fmt;
+use std::io::Write;
+
+use log::warn;
+use symphonia_core::errors::Result;
+use symphonia_core::packet::Packet;
+
+use crate::packet::packet_samples;
+use crate::{DEFAULT_SAMPLE_RATE, OpusHead, OpusHeadLayout, OpusTags};
+
+const OGG_MAGIC: &[u8; 4] = b"OggS";
+const MAX_SEGMENTS: usize = 255;
+/// Pages are flushed once they hold this many 48 kHz samples so that seeking stays granular.
+const MAX_PAGE_DURATION: u64 = DEFAULT_SAMPLE_RATE as u64;
+
+const FLAG_CONTINUED: u8 = 0x01;
+const FLAG_BOS: u8 = 0x02;
+const FLAG_EOS: u8 = 0x04;
+
+static CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, byte| {
+        (crc << 8) ^ CRC_TABLE[((crc >> 24) as u8 ^ byte) as usize]
+    })
+}
+
+/// Writes an Ogg Opus stream.
+///
+/// Packets can come straight from a Symphonia
+/// [`FormatReader`](symphonia_core::formats::FormatReader) or from an
+/// [`OpusEncoder`](crate::OpusEncoder). Packet timestamps are ignored and granule positions are
+/// derived from the duration of each packet instead, so the stream always starts at zero. The
+/// pre-skip comes from the identification header and the end trim of the last packet, in 48 kHz
+/// units, is applied to the final granule position.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc7845>.
+pub struct OggOpusWriter<W: Write> {
+    writer: W,
+    serial: u32,
+    sequence: u32,
+    /// Number of streams in each packet.
+    streams: u8,
+    /// Lacing values of the current page.
+    segments: Vec<u8>,
+    body: Vec<u8>,
+    /// Set if the current page starts with the rest of a packet from the previous page.
+    continued: bool,
+    /// Granule position after the last packet that ends on the current page.
+    page_granule: Option<u64>,
+    /// Granule position at the start of the current page.
+    page_start: u64,
+    /// Total duration of the packets written so far, including pre-skip.
+    granule: u64,
+    /// End trim of the last packet.
+    trim_end: u64,
+}
+
+impl<W: Write> fmt::Debug for OggOpusWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OggOpusWriter")
+            .field("writer", &"<writer>")
+            .field("serial", &self.serial)
+            .field("sequence", &self.sequence)
+            .field("streams", &self.streams)
+            .field("segments", &self.segments)
+            .field("body", &"<body>")
+            .field("continued", &self.continued)
+            .field("page_granule", &self.page_granule)
+            .field("page_start", &self.page_start)
+            .field("granule", &self.granule)
+            .field("trim_end", &self.trim_end)
+            .finish()
+    }
+}
+
+impl<W: Write> OggOpusWriter<W> {
+    /// Creates a writer for a logical stream with the given serial number and writes the header
+    /// pages.
+    pub fn try_new(writer: W, serial: u32, head: &OpusHead, tags: &OpusTags) -> Result<Self> {
+        let mut ogg_writer = Self {
+            writer,
+            serial,
+            sequence: 0,
+            streams: head.channel_mapping.streams,
+            segments: Vec::new(),
+            body: Vec::new(),
+            continued: false,
+            page_granule: None,
+            page_start: 0,
+            granule: 0,
+            trim_end: 0,
+        };
+
+        // Both headers must end their pages, and the identification header must be alone on the
+        // first one.
+        ogg_writer.append(&head.to_bytes(OpusHeadLayout::Ogg), 0)?;
+        ogg_writer.flush_page(FLAG_BOS)?;
+        ogg_writer.append(&tags.to_bytes(), 0)?;
+        ogg_writer.flush_page(0)?;
+        Ok(ogg_writer)
+    }
+
+    /// Writes an audio packet. Empty packets are skipped since their duration is unknown.
+    pub fn write_packet(&mut self, packet: &Packet) -> Result<()> {
+        if packet.data.is_empty() {
+            warn!("ogg: skipping empty packet");
+            return Ok(());
+        }
+        if self.trim_end > 0 {
+            warn!("ogg: ignoring end trim on a packet that isn't the last one");
+            self.trim_end = 0;
+        }
+
+        let dur = packet_samples(&packet.data, self.streams, DEFAULT_SAMPLE_RATE as u32)? as u64;
+        let segments = packet.data.len() / 255 + 1;
+        if self.page_granule.is_some()
+            && (self.segments.len() + segments > MAX_SEGMENTS
+                || self.granule - self.page_start >= MAX_PAGE_DURATION)
+        {
+            self.flush_page(0)?;
+        }
+
+        self.granule += dur;
+        self.trim_end = packet.trim_end.get().min(dur);
+        self.append(&packet.data, self.granule)
+    }
+
+    /// Writes the final page and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.page_granule = Some(self.granule - self.trim_end);
+        self.flush_page(FLAG_EOS)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Adds a packet to the current page, spilling onto new pages if the lacing table fills up.
+    fn append(&mut self, data: &[u8], granule: u64) -> Result<()> {
+        let mut remaining = data;
+        loop {
+            if self.segments.len() == MAX_SEGMENTS {
+                self.flush_page(0)?;
+                self.continued = true;
+            }
+            let len = remaining.len().min(255);
+            self.segments.push(len as u8);
+            self.body.extend_from_slice(&remaining[..len]);
+            remaining = &remaining[len..];
+            // A lacing value below 255 terminates the packet
+            if len < 255 {
+                break;
+            }
+        }
+        self.page_granule = Some(granule);
+        Ok(())
+    }
+
+    fn flush_page(&mut self, flags: u8) -> Result<()> {
+        let flags = if self.continued {
+            flags | FLAG_CONTINUED
+        } else {
+            flags
+        };
+        // A granule position of -1 marks a page where no packet ends
+        let granule = self.page_granule.map_or(-1, |granule| granule as i64);
+
+        let mut header = Vec::with_capacity(27 + self.segments.len());
+        header.extend_from_slice(OGG_MAGIC);
+        header.push(0);
+        header.push(flags);
+        header.extend_from_slice(&granule.to_le_bytes());
+        header.extend_from_slice(&self.serial.to_le_bytes());
+        header.extend_from_slice(&self.sequence.to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.push(self.segments.len() as u8);
+        header.extend_from_slice(&self.segments);
+
+        let crc = crc32(crc32(0, &header), &self.body);
+        header[22..26].copy_from_slice(&crc.to_le_bytes());
+        self.writer.write_all(&header)?;
+        self.writer.write_all(&self.body)?;
+
+        self.sequence += 1;
+        self.segments.clear();
+        self.body.clear();
+        self.continued = false;
+        self.page_granule = None;
+        self.page_start = self.granule;
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+use symphonia_core::errors::{Result, decode_error};
+
+/// Maximum size of a single compressed frame.
+const MAX_FRAME_LEN: usize = 1275;
+/// Maximum number of frames in a packet, which is 120 ms of 2.5 ms frames.
+const MAX_FRAMES: usize = 48;
+
+/// Coding mode of a packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpusMode {
+    /// Linear prediction, used for speech at low bitrates.
+    Silk,
+    /// SILK for the lower frequencies and CELT for the higher ones.
+    Hybrid,
+    /// Modified discrete cosine transform, used for music and low delay.
+    Celt,
+}
+
+/// Audio bandwidth of a packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Bandwidth {
+    /// 4 kHz
+    Narrowband,
+    /// 6 kHz
+    Mediumband,
+    /// 8 kHz
+    Wideband,
+    /// 12 kHz
+    SuperWideband,
+    /// 20 kHz
+    Fullband,
+}
+
+impl Bandwidth {
+    /// Lowest sample rate that can represent the full bandwidth.
+    pub fn sample_rate(self) -> u32 {
+        match self {
+            Self::Narrowband => 8000,
+            Self::Mediumband => 12000,
+            Self::Wideband => 16000,
+            Self::SuperWideband => 24000,
+            Self::Fullband => 48000,
+        }
+    }
+}
+
+/// A parsed Opus packet.
+///
+/// Parsing validates the packet against the constraints in
+/// [RFC 6716 section 3.4](https://www.rfc-editor.org/rfc/rfc6716#section-3.4) without decoding
+/// it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpusPacket<'a> {
+    toc: u8,
+    frames: [&'a [u8]; MAX_FRAMES],
+    frame_count: usize,
+    vbr: bool,
+    padding: usize,
+}
+
+impl<'a> OpusPacket<'a> {
+    /// Parses a packet that makes up the entire buffer.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        Self::parse_framing(data, false).map(|(packet, _)| packet)
+    }
+
+    /// Parses a packet using self-delimiting framing and returns it with the number of bytes it
+    /// occupies. Every stream except the last one in a multistream packet uses this framing.
+    ///
+    /// See <https://www.rfc-editor.org/rfc/rfc6716#appendix-B>.
+    pub fn parse_self_delimited(data: &'a [u8]) -> Result<(Self, usize)> {
+        Self::parse_framing(data, true)
+    }
+
+    fn parse_framing(data: &'a [u8], self_delimited: bool) -> Result<(Self, usize)> {
+        let mut reader = Reader { data };
+        let Some(toc) = reader.byte() else {
+            return decode_error("opus: empty packet");
+        };
+        let mut packet = Self {
+            toc,
+            frames: [&[]; MAX_FRAMES],
+            frame_count: 0,
+            vbr: false,
+            padding: 0,
+        };
+
+        // Length of each frame. The last one is implicit unless the packet is self-delimited, in
+        // which case it's stored after any other lengths.
+        let mut lens = [0; MAX_FRAMES];
+        match toc & 0x3 {
+            0 => {
+                packet.frame_count = 1;
+                lens[0] = if self_delimited {
+                    reader.len()?
+                } else {
+                    reader.data.len()
+                };
+            }
+            1 => {
+                packet.frame_count = 2;
+                let len = if self_delimited {
+                    reader.len()?
+                } else if reader.data.len().is_multiple_of(2) {
+                    reader.data.len() / 2
+                } else {
+                    return decode_error("opus: odd length for two equal-sized frames");
+                };
+                lens[..2].fill(len);
+            }
+            2 => {
+                packet.frame_count = 2;
+                packet.vbr = true;
+                lens[0] = reader.len()?;
+                lens[1] = if self_delimited {
+                    reader.len()?
+                } else {
+                    reader.remaining(lens[0])?
+                };
+            }
+            _ => {
+                let Some(count) = reader.byte() else {
+                    return decode_error("opus: missing frame count");
+                };
+                packet.vbr = count & 0x80 != 0;
+                packet.frame_count = (count & 0x3f) as usize;
+                if packet.frame_count == 0 {
+                    return decode_error("opus: packet has no frames");
+                }
+                if packet.frame_count * packet.frame_units() > MAX_FRAMES {
+                    return decode_error("opus: packet is longer than 120 ms");
+                }
+                if count & 0x40 != 0 {
+                    packet.padding = reader.padding()?;
+                }
+
+                let last = packet.frame_count - 1;
+                if packet.vbr {
+                    for len in &mut lens[..last] {
+                        *len = reader.len()?;
+                    }
+                    let sum = lens[..last].iter().sum::<usize>() + packet.padding;
+                    lens[last] = if self_delimited {
+                        reader.len()?
+                    } else {
+                        reader.remaining(sum)?
+                    };
+                } else {
+                    let len = if self_delimited {
+                        reader.len()?
+                    } else {
+                        let available = reader.remaining(packet.padding)?;
+                        if !available.is_multiple_of(packet.frame_count) {
+                            return decode_error("opus: uneven length for equal-sized frames");
+                        }
+                        available / packet.frame_count
+                    };
+                    lens[..packet.frame_count].fill(len);
+                }
+            }
+        }
+
+        for (frame, len) in packet.frames.iter_mut().zip(&lens[..packet.frame_count]) {
+            if *len > MAX_FRAME_LEN {
+                return decode_error("opus: frame is too large");
+            }
+            *frame = reader.take(*len)?;
+        }
+        reader.take(packet.padding)?;
+        if !self_delimited && !reader.data.is_empty() {
+            return decode_error("opus: trailing data after frames");
+        }
+        Ok((packet, data.len() - reader.data.len()))
+    }
+
+    /// The table-of-contents byte.
+    pub fn toc(&self) -> u8 {
+        self.toc
+    }
+
+    /// Configuration number from the TOC byte, which determines the mode, bandwidth and frame
+    /// duration.
+    pub fn config(&self) -> u8 {
+        self.toc >> 3
+    }
+
+    /// Coding mode.
+    pub fn mode(&self) -> OpusMode {
+        match self.config() {
+            0..=11 => OpusMode::Silk,
+            12..=15 => OpusMode::Hybrid,
+            _ => OpusMode::Celt,
+        }
+    }
+
+    /// Audio bandwidth.
+    pub fn bandwidth(&self) -> Bandwidth {
+        match self.config() {
+            0..=3 | 16..=19 => Bandwidth::Narrowband,
+            4..=7 => Bandwidth::Mediumband,
+            8..=11 | 20..=23 => Bandwidth::Wideband,
+            12..=13 | 24..=27 => Bandwidth::SuperWideband,
+            _ => Bandwidth::Fullband,
+        }
+    }
+
+    /// Returns `true` if the frames are coded in stereo.
+    pub fn is_stereo(&self) -> bool {
+        self.toc & 0x4 != 0
+    }
+
+    /// Returns `true` if the frames may have different sizes.
+    pub fn is_vbr(&self) -> bool {
+        self.vbr
+    }
+
+    /// Number of frames in the packet.
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Compressed data of each frame. Frames can be empty, which signals a lost frame or
+    /// discontinuous transmission.
+    pub fn frames(&self) -> &[&'a [u8]] {
+        &self.frames[..self.frame_count]
+    }
+
+    /// Number of padding bytes.
+    pub fn padding(&self) -> usize {
+        self.padding
+    }
+
+    /// Number of samples per channel in each frame at the given sample rate.
+    pub fn frame_samples(&self, sample_rate: u32) -> usize {
+        self.frame_units() * sample_rate as usize / 400
+    }
+
+    /// Number of samples per channel in the packet at the given sample rate.
+    pub fn samples(&self, sample_rate: u32) -> usize {
+        self.frame_count * self.frame_samples(sample_rate)
+    }
+
+    /// Frame duration in units of 2.5 ms.
+    fn frame_units(&self) -> usize {
+        let config = self.config() as usize;
+        match config {
+            // SILK: 10, 20, 40 or 60 ms
+            0..=11 => [4, 8, 16, 24][config % 4],
+            // Hybrid: 10 or 20 ms
+            12..=15 => [4, 8][config % 2],
+            // CELT: 2.5, 5, 10 or 20 ms
+            _ => [1, 2, 4, 8][config % 4],
+        }
+    }
+}
+
+/// Number of samples per channel in a packet with the given number of streams. Every stream in a
+/// multistream packet has the same duration, so only the first one is parsed.
+pub(crate) fn packet_samples(data: &[u8], streams: u8, sample_rate: u32) -> Result<usize> {
+    let packet = match streams {
+        0 | 1 => OpusPacket::parse(data)?,
+        _ => OpusPacket::parse_self_delimited(data)?.0,
+    };
+    Ok(packet.samples(sample_rate))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let (byte, rest) = self.data.split_first()?;
+        self.data = rest;
+        Some(*byte)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            return decode_error("opus: packet is truncated");
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    /// Reads a frame length, which is stored in one or two bytes.
+    fn len(&mut self) -> Result<usize> {
+        match self.byte() {
+            Some(byte) if byte < 252 => Ok(byte as usize),
+            Some(byte) => match self.byte() {
+                Some(next) => Ok(next as usize * 4 + byte as usize),
+                None => decode_error("opus: truncated frame length"),
+            },
+            None => decode_error("opus: missing frame length"),
+        }
+    }
+
+    /// Reads the padding length, which is a sequence of bytes where 255 adds 254 bytes and
+    /// continues the sequence.
+    fn padding(&mut self) -> Result<usize> {
+        let mut padding = 0;
+        loop {
+            match self.byte() {
+                Some(255) => padding += 254,
+                Some(byte) => return Ok(padding + byte as usize),
+                None => return decode_error("opus: truncated padding length"),
+            }
+        }
+    }
+
+    /// Length of the remaining data after reserving `reserved` bytes.
+    fn remaining(&self, reserved: usize) -> Result<usize> {
+        match self.data.len().checked_sub(reserved) {
+            Some(len) => Ok(len),
+            None => decode_error("opus: packet is truncated"),
+        }
+    }
+}
@@ -1,24 +1,1760 @@
 use std::fs::File;
+use std::io::Cursor;
 
+use symphonia::core::audio::{AsGenericAudioBufferRef, AudioBuffer, AudioMut, AudioSpec, layouts};
 use symphonia::core::codecs::CodecParameters;
-use symphonia::core::codecs::registry::CodecRegistry;
-use symphonia::core::formats::TrackType;
-use symphonia::core::formats::probe::Hint;
-use symphonia::core::io::MediaSourceStream;
+use symphonia::core::codecs::audio::well_known::profiles::{
+    CODEC_PROFILE_AAC_HE, CODEC_PROFILE_AAC_HE_V2, CODEC_PROFILE_AAC_USAC,
+};
+use symphonia::core::codecs::audio::well_known::{CODEC_ID_AAC, CODEC_ID_OPUS};
+use symphonia::core::codecs::audio::{AudioCodecParameters, AudioDecoder};
+use symphonia::core::codecs::registry::{CodecRegistry, RegisterableAudioDecoder};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::probe::{Hint, Probe};
+use symphonia::core::formats::{FormatReader, SeekMode, SeekTo, TrackType};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::packet::Packet;
+use symphonia::core::units::{Duration, Time, Timestamp};
 use symphonia::default::get_probe;
-use symphonia_adapter_fdk_aac::AacDecoder;
-use symphonia_adapter_libopus::OpusDecoder;
+use symphonia_adapter_fdk_aac::meta::{
+    AudioSpecificConfig, M4AType, SbrConfig, SbrSignaling, SpecificConfig,
+};
+use symphonia_adapter_fdk_aac::{
+    AacDecoder, AacDecoderOptions, CODEC_ID_AAC_LATM, CODEC_PROFILE_AAC_ADAPTER_BASE,
+    CODEC_PROFILE_AAC_ELD, CODEC_PROFILE_AAC_LD, DrcEffectType, DrcOptions, FORMAT_ID_LOAS,
+    GaplessInfo, LoasReader,
+};
+use symphonia_adapter_libopus::{
+    Application, Bandwidth, Bitrate, ChannelMapping, EncodeError, FrameDuration, OggOpusWriter,
+    OpusDecoder, OpusDecoderOptions, OpusEncoder, OpusEncoderOptions, OpusHead, OpusHeadLayout,
+    OpusMode, OpusPacket, OpusTags, OutputGain, Signal,
+};
 
 #[test]
 fn test_decode_aac() {
     test_decode(File::open("../assets/music.m4a").unwrap());
 }
 
+#[test]
+fn test_aac_adts_fallback() {
+    let raw = decode_aac(|_| {});
+    // Without an AudioSpecificConfig, ADTS headers are synthesized from the codec parameters
+    let adts = decode_aac(|params| params.extra_data = None);
+    assert!(!raw.is_empty());
+    assert_eq!(raw, adts);
+}
+
+#[test]
+fn test_aac_adts_passthrough() {
+    let raw = decode_aac(|_| {});
+    let (_, decoder) = open_aac(|_| {});
+    let asc = decoder.codec_params().extra_data.clone().unwrap();
+    let framed = |_, packet: Packet| {
+        let data = adts_frame(&asc, &packet.data);
+        Packet::new(packet.track_id, packet.pts, packet.dur, data)
+    };
+
+    // Packets that keep their ADTS headers are decoded with or without a config
+    let (mut reader, mut decoder) = open_aac(|_| {});
+    let with_config = decode_aac_frames(&mut *reader, &mut *decoder, usize::MAX, framed).concat();
+    assert_eq!(raw, with_config);
+    let (mut reader, mut decoder) = open_aac(|params| params.extra_data = None);
+    let without_config =
+        decode_aac_frames(&mut *reader, &mut *decoder, usize::MAX, framed).concat();
+    assert_eq!(raw, without_config);
+
+    // Packets can contain several frames
+    let (mut reader, mut decoder) = open_aac(|params| params.extra_data = None);
+    let mut concatenated = Vec::new();
+    while let Some(first) = reader.next_packet().unwrap() {
+        let mut data = adts_frame(&asc, &first.data);
+        let mut dur = first.dur.get();
+        if let Some(second) = reader.next_packet().unwrap() {
+            data.extend(adts_frame(&asc, &second.data));
+            dur += second.dur.get();
+        }
+        let packet = Packet::new(first.track_id, first.pts, Duration::new(dur), data);
+        let mut decoded = Vec::new();
+        decoder
+            .decode(&packet)
+            .unwrap()
+            .copy_to_vec_interleaved(&mut decoded);
+        concatenated.extend_from_slice(&decoded);
+    }
+    assert_eq!(raw, concatenated);
+
+    // Frames must fit in the packet
+    let (mut reader, mut decoder) = open_aac(|_| {});
+    let packet = reader.next_packet().unwrap().unwrap();
+    let mut data = adts_frame(&asc, &packet.data);
+    data.pop();
+    let packet = Packet::new(packet.track_id, packet.pts, packet.dur, data);
+    assert!(decoder.decode(&packet).is_err());
+}
+
+#[test]
+fn test_aac_esds_extra_data() {
+    let raw = decode_aac(|_| {});
+    let (_, decoder) = open_aac(|_| {});
+    let asc = decoder.codec_params().extra_data.clone().unwrap();
+
+    // Descriptors from the esds box are unwrapped down to the DecoderSpecificInfo
+    let specific_info = descriptor(0x05, &asc);
+    let mut config_body = vec![0x40, 0x15, 0x00, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+    config_body.extend_from_slice(&specific_info);
+    let config = descriptor(0x04, &config_body);
+    let mut es_body = vec![0x00, 0x01, 0x00];
+    es_body.extend_from_slice(&config);
+    es_body.extend_from_slice(&descriptor(0x06, &[0x02]));
+    let es = descriptor(0x03, &es_body);
+    let mut esds = ((es.len() + 12) as u32).to_be_bytes().to_vec();
+    esds.extend_from_slice(b"esds");
+    esds.extend_from_slice(&[0; 4]);
+    esds.extend_from_slice(&es);
+    let mut full_box = vec![0; 4];
+    full_box.extend_from_slice(&es);
+
+    for extra_data in [specific_info, config, es, esds, full_box] {
+        let decoded = decode_aac(|params| params.extra_data = Some(extra_data.into()));
+        assert_eq!(raw, decoded);
+    }
+
+    // With unusable extra data, the config has to come from the ADTS headers of the packets
+    let framed = |_, packet: Packet| {
+        let data = adts_frame(&asc, &packet.data);
+        Packet::new(packet.track_id, packet.pts, packet.dur, data)
+    };
+    for extra_data in [&[][..], &[0x12], &[0x05, 0x00], &[0x00, 0x00]] {
+        let (mut reader, mut decoder) = open_aac(|params| {
+            params.extra_data = Some(extra_data.into());
+            params.channels = None;
+            params.sample_rate = None;
+        });
+        let sniffed = decode_aac_frames(&mut *reader, &mut *decoder, usize::MAX, framed).concat();
+        assert_eq!(raw, sniffed);
+
+        // Raw packets aren't decoded with a config guessed from the codec parameters
+        let (mut reader, mut decoder) =
+            open_aac(|params| params.extra_data = Some(extra_data.into()));
+        let packet = reader.next_packet().unwrap().unwrap();
+        assert!(decoder.decode(&packet).is_err());
+    }
+    let (mut reader, mut decoder) = open_aac(|params| {
+        params.extra_data = None;
+        params.channels = None;
+    });
+    let packet = reader.next_packet().unwrap().unwrap();
+    assert!(decoder.decode(&packet).is_err());
+}
+
+#[test]
+fn test_aac_frame_length_change() {
+    // AAC-LD with 480 sample frames followed by 512 sample frames
+    let file = File::open("../assets/aac-ld-frame-length.loas").unwrap();
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut reader = LoasReader::try_new(mss, Default::default()).unwrap();
+    let track = reader.default_track(TrackType::Audio).unwrap();
+    let Some(CodecParameters::Audio(codec_params)) = track.codec_params.as_ref() else {
+        panic!("invalid params");
+    };
+    let mut decoder =
+        AacDecoder::try_new_with_options(codec_params, &Default::default(), &Default::default())
+            .unwrap();
+    let mut frames = 0;
+    while let Some(packet) = reader.next_packet().unwrap() {
+        frames += decoder.decode(&packet).unwrap().frames();
+        // Only the frame length changes, so the stream keeps going in the same format
+        assert!(!decoder.config_changed());
+    }
+    assert_eq!(frames, 101 * 480 + 95 * 512);
+    assert_eq!(decoder.codec_params().frames_per_block, Some(512));
+}
+
+#[test]
+fn test_aac_config_change() {
+    let (mut reader, decoder) = open_aac(|params| params.extra_data = None);
+    let asc = [0x12, 0x10];
+    // 44.1 kHz and 48 kHz use the same scalefactor bands, so the frames can be relabeled
+    let relabeled = [0x11, 0x90];
+    let mut decoder = AacDecoder::try_new_with_options(
+        decoder.codec_params(),
+        &Default::default(),
+        &Default::default(),
+    )
+    .unwrap();
+
+    let mut packet_num = 0;
+    while let Some(packet) = reader.next_packet().unwrap() {
+        let asc = if packet_num < 20 { &asc } else { &relabeled };
+        let data = adts_frame(asc, &packet.data);
+        let packet = Packet::new(packet.track_id, packet.pts, packet.dur, data);
+        let rate = decoder.decode(&packet).unwrap().spec().rate();
+        assert_eq!(decoder.config_changed(), packet_num == 20);
+        assert_eq!(rate, if packet_num < 20 { 44100 } else { 48000 });
+        packet_num += 1;
+    }
+    assert!(packet_num > 20);
+}
+
+#[test]
+fn test_aac_implicit_sbr_params() {
+    for (path, channels, profile) in [
+        (
+            "../assets/aac-he.aac",
+            layouts::CHANNEL_LAYOUT_STEREO,
+            CODEC_PROFILE_AAC_HE,
+        ),
+        (
+            "../assets/aac-hev2.aac",
+            layouts::CHANNEL_LAYOUT_MONO,
+            CODEC_PROFILE_AAC_HE_V2,
+        ),
+    ] {
+        // ADTS headers can't signal SBR, so they describe the core stream
+        let mut params = AudioCodecParameters::new();
+        params
+            .for_codec(CODEC_ID_AAC)
+            .with_sample_rate(22050)
+            .with_channels(channels.clone());
+        let mut decoder = AacDecoder::try_registry_new(&params, &Default::default()).unwrap();
+        assert_eq!(decoder.codec_params().sample_rate, Some(22050));
+        assert_eq!(decoder.codec_params().channels, Some(channels));
+
+        for packet in adts_packets(path, 2048) {
+            decoder.decode(&packet).unwrap();
+        }
+        let params = decoder.codec_params();
+        assert_eq!(params.sample_rate, Some(44100));
+        assert_eq!(params.channels, Some(layouts::CHANNEL_LAYOUT_STEREO));
+        assert_eq!(params.frames_per_block, Some(2048));
+        assert_eq!(params.profile, Some(profile));
+    }
+}
+
+#[test]
+fn test_aac_multichannel() {
+    for (path, asc, layout, input_channels) in [
+        (
+            "../assets/aac-5p1.aac",
+            [0x11, 0xb0],
+            layouts::CHANNEL_LAYOUT_AAC_5P1,
+            // Encoded in MPEG order: C L R Ls Rs LFE
+            vec![1, 2, 0, 5, 3, 4],
+        ),
+        (
+            "../assets/aac-7p1.aac",
+            [0x11, 0xb8],
+            layouts::CHANNEL_LAYOUT_AAC_7P1,
+            // Encoded in MPEG order: C Lc Rc L R Ls Rs LFE
+            vec![3, 4, 0, 7, 5, 6, 1, 2],
+        ),
+    ] {
+        let num_channels = input_channels.len();
+        for with_config in [true, false] {
+            let mut params = AudioCodecParameters::new();
+            params.for_codec(CODEC_ID_AAC).with_sample_rate(48000);
+            if with_config {
+                params.with_extra_data(asc.into());
+            } else {
+                params.with_channels(layout.clone());
+            }
+            let mut decoder = AacDecoder::try_registry_new(&params, &Default::default()).unwrap();
+
+            let mut planes = vec![Vec::new(); num_channels];
+            for packet in adts_packets(path, 1024) {
+                let packet = if with_config {
+                    let payload = packet.data[7..].to_vec();
+                    Packet::new(packet.track_id, packet.pts, packet.dur, payload)
+                } else {
+                    packet
+                };
+                let decoded = decoder.decode(&packet).unwrap();
+                assert_eq!(decoded.spec().channels(), &layout);
+                let mut samples: Vec<i16> = Vec::new();
+                decoded.copy_to_vec_interleaved(&mut samples);
+                for frame in samples.chunks(num_channels) {
+                    for (plane, sample) in planes.iter_mut().zip(frame) {
+                        plane.push(f32::from(*sample) / 32768.0);
+                    }
+                }
+            }
+
+            // Channel configuration 7 has 8 channels
+            assert_eq!(decoder.codec_params().channels, Some(layout.clone()));
+
+            // Each input channel was encoded with its own level
+            for (canonical_idx, input_idx) in input_channels.iter().enumerate() {
+                let plane = &planes[canonical_idx][4800..];
+                let rms =
+                    (plane.iter().map(|s| s.powi(2)).sum::<f32>() / plane.len() as f32).sqrt();
+                let expected = 0.05 * (input_idx + 1) as f32 / 2f32.sqrt();
+                assert!(
+                    (rms - expected).abs() < expected * 0.05,
+                    "{path} channel {canonical_idx} has rms {rms}, expected {expected}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_aac_seek_reset() {
+    let seek = |reader: &mut dyn FormatReader| {
+        let time = Time::try_new(2, 0).unwrap();
+        reader
+            .seek(
+                SeekMode::Coarse,
+                SeekTo::Time {
+                    time,
+                    track_id: None,
+                },
+            )
+            .unwrap();
+    };
+
+    // The demuxer can trim the first packet to land on the exact seek target
+    let trim_first = |i: usize, mut packet: Packet| {
+        if i == 0 {
+            packet.trim_start = Duration::new(300);
+        }
+        packet
+    };
+
+    let (mut reader, mut decoder) = open_aac(|_| {});
+    // Build up some decoder state before seeking
+    decode_aac_frames(&mut *reader, &mut *decoder, 20, |_, packet| packet);
+    seek(&mut *reader);
+    decoder.reset();
+    let seeked = decode_aac_frames(&mut *reader, &mut *decoder, 20, trim_first);
+
+    let (mut reader, mut decoder) = open_aac(|_| {});
+    seek(&mut *reader);
+    let fresh = decode_aac_frames(&mut *reader, &mut *decoder, 20, trim_first);
+
+    // The output lines up with a decode started from the same position
+    assert_eq!(
+        seeked.iter().map(Vec::len).collect::<Vec<_>>(),
+        fresh.iter().map(Vec::len).collect::<Vec<_>>()
+    );
+    let first = seeked.iter().position(|frame| !frame.is_empty()).unwrap();
+    // Only the first frame after the reset is faded in
+    assert!(
+        seeked[first]
+            .iter()
+            .zip(&fresh[first])
+            .all(|(seeked, fresh)| seeked.unsigned_abs() <= fresh.unsigned_abs())
+    );
+    assert_ne!(seeked[first], fresh[first]);
+    assert_eq!(seeked[first + 1..], fresh[first + 1..]);
+}
+
+#[test]
+fn test_aac_drain() {
+    let (mut reader, mut decoder) = open_aac(|_| {});
+    let packets = decode_aac_frames(&mut *reader, &mut *decoder, usize::MAX, |_, packet| packet);
+    let channels = decoder.last_decoded().spec().channels().count();
+    let full_len = packets.len() * 1024 * channels;
+    let mut drained = Vec::new();
+    decoder.finalize();
+    decoder.last_decoded().copy_to_vec_interleaved(&mut drained);
+    // The decoder delay is trimmed from the start and drained at the end
+    assert!(drained.iter().any(|sample| *sample != 0));
+    assert_eq!(packets.concat().len() + drained.len(), full_len);
+
+    let (mut reader, mut decoder) = open_aac(|_| {});
+    let last = packets.len() - 1;
+    let mut trimmed =
+        decode_aac_frames(&mut *reader, &mut *decoder, usize::MAX, |i, mut packet| {
+            if i == 0 {
+                packet.trim_start = Duration::new(1000);
+            } else if i == last {
+                packet.trim_end = Duration::new(500);
+            }
+            packet
+        })
+        .concat();
+    decoder.finalize();
+    decoder.last_decoded().copy_to_vec_interleaved(&mut drained);
+    trimmed.extend_from_slice(&drained);
+    assert_eq!(trimmed.len(), full_len - 1500 * channels);
+}
+
+#[test]
+fn test_aac_auto_trim() {
+    let (mut reader, mut decoder) = open_aac(|_| {});
+    let packets = decode_aac_frames(&mut *reader, &mut *decoder, usize::MAX, |_, packet| packet);
+    let channels = decoder.last_decoded().spec().channels().count();
+    let full_len = packets.len() * 1024;
+
+    // Without gapless info, the usual priming for the profile is trimmed
+    let samples = decode_aac_with_options(&AacDecoderOptions::default().auto_trim(true));
+    assert_eq!(samples.len(), (full_len - 1024) * channels);
+    let decode_he = |aac_opts: &AacDecoderOptions| {
+        let mut params = AudioCodecParameters::new();
+        params
+            .for_codec(CODEC_ID_AAC)
+            .with_sample_rate(22050)
+            .with_channels(layouts::CHANNEL_LAYOUT_STEREO);
+        let mut decoder =
+            AacDecoder::try_new_with_options(&params, &Default::default(), aac_opts).unwrap();
+        adts_packets("../assets/aac-he.aac", 1024)
+            .iter()
+            .map(|packet| decoder.decode(packet).unwrap().frames())
+            .sum::<usize>()
+    };
+    let untrimmed = decode_he(&Default::default());
+    let trimmed = decode_he(&AacDecoderOptions::default().auto_trim(true));
+    assert_eq!(untrimmed - trimmed, 2112);
+
+    let smpb = format!(
+        " 00000000 00000840 {:08X} {:016X} 00000000",
+        full_len - 2112 - 44100,
+        44100
+    );
+    let info = GaplessInfo::from_itunsmpb(&smpb).unwrap();
+    assert_eq!(info.delay, 2112);
+    let opts = AacDecoderOptions::default()
+        .auto_trim(true)
+        .gapless_info(info);
+    let samples = decode_aac_with_options(&opts);
+    assert_eq!(samples.len(), 44100 * channels);
+
+    assert!(GaplessInfo::from_itunsmpb(" 00000000 00000840").is_none());
+}
+
+#[test]
+fn test_aac_drc() {
+    let (mut reader, decoder) = open_aac(|_| {});
+    let params = decoder.codec_params().clone();
+    let drc = DrcOptions::default()
+        .target_reference_level(64)
+        .boost_factor(127)
+        .attenuation_factor(127)
+        .effect_type(DrcEffectType::NoisyEnvironment);
+    let mut decoder = AacDecoder::try_new_with_options(
+        &params,
+        &Default::default(),
+        &AacDecoderOptions::default().drc(drc),
+    )
+    .unwrap();
+    assert!(decoder.drc_info().is_none());
+
+    decode_aac_frames(&mut *reader, &mut decoder, 1, |_, packet| packet);
+    let info = decoder.drc_info().unwrap();
+    assert!(!info.drc_present);
+    assert_eq!(info.program_reference_level, None);
+
+    // The asset carries MPEG DRC data with a program reference level of -12 dB
+    let decode_drc = |drc: DrcOptions| {
+        let mut params = AudioCodecParameters::new();
+        params
+            .for_codec(CODEC_ID_AAC)
+            .with_sample_rate(44100)
+            .with_channels(layouts::CHANNEL_LAYOUT_STEREO);
+        let mut decoder = AacDecoder::try_new_with_options(
+            &params,
+            &Default::default(),
+            &AacDecoderOptions::default().drc(drc),
+        )
+        .unwrap();
+        let mut samples = Vec::new();
+        let mut decoded = Vec::new();
+        for packet in adts_packets("../assets/aac-drc.aac", 1024) {
+            decoder
+                .decode(&packet)
+                .unwrap()
+                .copy_to_vec_interleaved(&mut decoded);
+            samples.extend_from_slice(&decoded);
+        }
+        let info = decoder.drc_info().unwrap();
+        assert!(info.drc_present);
+        assert_eq!(info.program_reference_level, Some(48));
+        samples
+            .iter()
+            .map(|s: &i16| s.unsigned_abs())
+            .max()
+            .unwrap()
+    };
+    let unnormalized = decode_drc(DrcOptions::default().target_reference_level(-1));
+    // Normalizing to -16 dB and -25 dB attenuates the output by roughly 4 dB and 13 dB
+    let normalized = decode_drc(DrcOptions::default().target_reference_level(64));
+    let quieter = decode_drc(DrcOptions::default().target_reference_level(100));
+    assert!(
+        normalized < unnormalized * 3 / 4,
+        "{normalized} {unnormalized}"
+    );
+    assert!(quieter < normalized / 2, "{quieter} {normalized}");
+
+    // Out of range values are rejected when the decoder is created
+    let opts = AacDecoderOptions::default().drc(DrcOptions::default().boost_factor(200));
+    assert!(AacDecoder::try_new_with_options(&params, &Default::default(), &opts).is_err());
+}
+
+#[test]
+fn test_aac_stream_info() {
+    let (mut reader, decoder) = open_aac(|_| {});
+    let mut decoder = AacDecoder::try_new_with_options(
+        decoder.codec_params(),
+        &Default::default(),
+        &Default::default(),
+    )
+    .unwrap();
+    assert!(decoder.stream_info().is_none());
+
+    decode_aac_frames(&mut *reader, &mut decoder, 2, |_, packet| packet);
+    let info = decoder.stream_info().unwrap();
+    assert_eq!(info.object_type, M4AType::Lc);
+    assert_eq!(info.sample_rate, 44100);
+    assert_eq!(info.core_sample_rate, 44100);
+    assert_eq!(info.channels, 2);
+    assert_eq!(info.channel_config, 2);
+    assert_eq!(info.frame_size, 1024);
+    assert!(!info.sbr_present && !info.ps_present);
+    assert_eq!(info.total_frames, 2);
+    assert_eq!(info.bad_frames, 0);
+    assert_eq!(
+        info.to_string(),
+        "MPEG 4 Audio AAC LC, 44100 Hz, 2 channels, 1024 samples per frame"
+    );
+
+    let mut params = AudioCodecParameters::new();
+    params
+        .for_codec(CODEC_ID_AAC)
+        .with_sample_rate(22050)
+        .with_channels(layouts::CHANNEL_LAYOUT_MONO);
+    let mut decoder =
+        AacDecoder::try_new_with_options(&params, &Default::default(), &Default::default())
+            .unwrap();
+    for packet in adts_packets("../assets/aac-hev2.aac", 2048) {
+        decoder.decode(&packet).unwrap();
+    }
+    let info = decoder.stream_info().unwrap();
+    assert!(info.sbr_present && info.ps_present);
+    assert_eq!((info.sample_rate, info.core_sample_rate), (44100, 22050));
+    assert_eq!((info.channels, info.core_channels), (2, 1));
+    assert_eq!((info.frame_size, info.core_frame_size), (2048, 1024));
+    assert!(info.bitrate > 0);
+}
+
+#[test]
+fn test_aac_usac_config() {
+    // AudioSpecificConfig for 48 kHz mono USAC with 1024 sample frames and a single SCE
+    let asc = [0xf9, 0x46, 0x23, 0x21, 0x00, 0x00];
+    let mut params = AudioCodecParameters::new();
+    params
+        .for_codec(CODEC_ID_AAC)
+        .with_profile(CODEC_PROFILE_AAC_USAC)
+        .with_extra_data(Box::new(asc));
+    let mut decoder = AacDecoder::try_registry_new(&params, &Default::default()).unwrap();
+    let spec = decoder.last_decoded().spec().clone();
+    assert_eq!(spec.rate(), 48000);
+    assert_eq!(spec.channels(), &layouts::CHANNEL_LAYOUT_MONO);
+
+    // An independent frame with a single FD channel stream and no spectral data, since there's
+    // no USAC encoder to make a real stream with. It decodes to silence.
+    let frame = [0x8c, 0x80, 0x00];
+    let mut samples = 0;
+    for i in 0..10 {
+        let packet = Packet::new(
+            0,
+            Timestamp::new(i * 1024),
+            Duration::new(1024),
+            frame.to_vec(),
+        );
+        samples += decoder.decode(&packet).unwrap().frames();
+    }
+    decoder.finalize();
+    samples += decoder.last_decoded().frames();
+    assert_eq!(samples, 10 * 1024);
+    let decoded_params = decoder.codec_params();
+    assert_eq!(decoded_params.sample_rate, Some(48000));
+    assert_eq!(decoded_params.frames_per_block, Some(1024));
+    assert_eq!(decoded_params.profile, Some(CODEC_PROFILE_AAC_USAC));
+    assert_eq!(decoded_params.channels, Some(layouts::CHANNEL_LAYOUT_MONO));
+
+    // USAC can't be signaled in ADTS headers
+    params.extra_data = None;
+    params
+        .with_sample_rate(48000)
+        .with_channels(layouts::CHANNEL_LAYOUT_MONO);
+    assert!(AacDecoder::try_registry_new(&params, &Default::default()).is_err());
+
+    assert!(
+        AacDecoder::supported_codecs()[0]
+            .info
+            .profiles
+            .iter()
+            .any(|profile| profile.profile == CODEC_PROFILE_AAC_USAC)
+    );
+}
+
+#[test]
+fn test_aac_low_delay() {
+    for (path, profile, frame_len) in [
+        ("../assets/aac-ld.m4a", CODEC_PROFILE_AAC_LD, 480),
+        ("../assets/aac-eld.m4a", CODEC_PROFILE_AAC_ELD, 512),
+    ] {
+        let (mut reader, mut decoder) = open_aac_file(path, |_| {});
+        let frames = decode_aac_frames(&mut *reader, &mut *decoder, usize::MAX, |_, packet| packet);
+        let spec = decoder.last_decoded().spec().clone();
+        assert_eq!(spec.rate(), 44100);
+        assert_eq!(spec.channels(), &layouts::CHANNEL_LAYOUT_STEREO);
+        // Symphonia has no low delay profiles, so they're reported with this crate's codes
+        assert_eq!(decoder.codec_params().profile, Some(profile));
+        assert!(profile.get() >= CODEC_PROFILE_AAC_ADAPTER_BASE);
+
+        let mut samples = frames.concat();
+        decoder.finalize();
+        let mut drained = Vec::new();
+        decoder.last_decoded().copy_to_vec_interleaved(&mut drained);
+        samples.extend_from_slice(&drained);
+        assert_eq!(samples.len(), frames.len() * frame_len * 2);
+        assert!(samples.iter().any(|s| s.unsigned_abs() > 1000));
+
+        // Raw access units that happen to start with an ADTS sync word aren't unwrapped
+        let (mut reader, mut decoder) = open_aac_file(path, |_| {});
+        let packet = reader.next_packet().unwrap().unwrap();
+        let mut data = packet.data.to_vec();
+        data[..2].copy_from_slice(&[0xff, 0xf1]);
+        let packet = Packet::new(packet.track_id, packet.pts, packet.dur, data);
+        // The corrupted bits are passed on to FDK rather than rejected as an invalid ADTS frame
+        if let Err(e) = decoder.decode(&packet) {
+            assert!(!e.to_string().contains("adts"), "{e}");
+        }
+
+        // The container doesn't signal the profile, and ADTS headers can't either
+        let (_, decoder) = open_aac_file(path, |_| {});
+        let mut params = decoder.codec_params().clone();
+        params.extra_data = None;
+        params.with_profile(profile);
+        assert!(AacDecoder::try_registry_new(&params, &Default::default()).is_err());
+    }
+}
+
+#[test]
+fn test_aac_loas() {
+    let open = || {
+        let file = File::open("../assets/aac-lc.loas").unwrap();
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut probe = Probe::new();
+        probe.register_format::<LoasReader>();
+        let reader = probe
+            .probe(&Hint::new(), mss, Default::default(), Default::default())
+            .unwrap();
+        assert_eq!(reader.format_info().format, FORMAT_ID_LOAS);
+
+        let track = reader.default_track(TrackType::Audio).unwrap();
+        let Some(CodecParameters::Audio(codec_params)) = track.codec_params.as_ref() else {
+            panic!("invalid params");
+        };
+        assert_eq!(codec_params.codec, CODEC_ID_AAC_LATM);
+        assert_eq!(codec_params.sample_rate, Some(44100));
+        assert_eq!(codec_params.channels, Some(layouts::CHANNEL_LAYOUT_STEREO));
+        let decoder = AacDecoder::try_registry_new(codec_params, &Default::default()).unwrap();
+        (reader, decoder)
+    };
+
+    let (mut reader, mut decoder) = open();
+    let frames = decode_aac_frames(&mut *reader, &mut *decoder, usize::MAX, |_, packet| packet);
+    // The recording starts mid-stream, so the frames before the first StreamMuxConfig are skipped
+    assert_eq!(frames.len(), 79);
+    let mut samples = frames.concat();
+    decoder.finalize();
+    let mut drained = Vec::new();
+    decoder.last_decoded().copy_to_vec_interleaved(&mut drained);
+    samples.extend_from_slice(&drained);
+    assert_eq!(samples.len(), frames.len() * 1024 * 2);
+    assert!(samples.iter().any(|s| s.unsigned_abs() > 1000));
+
+    // LOAS frames are also detected in AAC packets without a usable config
+    let (mut reader, decoder) = open();
+    let mut params = decoder.codec_params().clone();
+    params.codec = CODEC_ID_AAC;
+    params.extra_data = Some([0x12].into());
+    params.channels = None;
+    params.sample_rate = None;
+    let mut decoder = AacDecoder::try_registry_new(&params, &Default::default()).unwrap();
+    let mut sniffed =
+        decode_aac_frames(&mut *reader, &mut *decoder, usize::MAX, |_, packet| packet).concat();
+    decoder.finalize();
+    let mut drained = Vec::new();
+    decoder.last_decoded().copy_to_vec_interleaved(&mut drained);
+    sniffed.extend_from_slice(&drained);
+    assert_eq!(sniffed, samples);
+
+    // Packets can contain several frames
+    let (mut reader, mut decoder) = open();
+    let first = reader.next_packet().unwrap().unwrap();
+    let mut data = first.data.to_vec();
+    let mut dur = first.dur.get();
+    while let Some(packet) = reader.next_packet().unwrap() {
+        data.extend_from_slice(&packet.data);
+        dur += packet.dur.get();
+    }
+    let packet = Packet::new(first.track_id, first.pts, Duration::new(dur), data);
+    let mut combined = Vec::new();
+    decoder
+        .decode(&packet)
+        .unwrap()
+        .copy_to_vec_interleaved(&mut combined);
+    decoder.finalize();
+    let mut drained = Vec::new();
+    decoder.last_decoded().copy_to_vec_interleaved(&mut drained);
+    combined.extend_from_slice(&drained);
+    assert_eq!(combined, samples);
+
+    // Packets larger than the decoder's input buffer are filled in as frames are decoded
+    let mut decode_all = |packets: &[Packet]| {
+        decoder.reset();
+        let mut samples: Vec<i16> = Vec::new();
+        for packet in packets {
+            let mut decoded = Vec::new();
+            decoder
+                .decode(packet)
+                .unwrap()
+                .copy_to_vec_interleaved(&mut decoded);
+            samples.extend_from_slice(&decoded);
+        }
+        decoder.finalize();
+        let mut drained = Vec::new();
+        decoder.last_decoded().copy_to_vec_interleaved(&mut drained);
+        samples.extend_from_slice(&drained);
+        samples
+    };
+    let repeated = Packet::new(
+        packet.track_id,
+        packet.pts,
+        Duration::new(dur * 4),
+        packet.data.repeat(4),
+    );
+    let separate = decode_all(&[packet.clone(), packet.clone(), packet.clone(), packet]);
+    let combined = decode_all(&[repeated]);
+    // FDK conceals the joins between the copies differently, so only the audio before the first
+    // join is compared
+    assert_eq!(separate.len(), combined.len());
+    assert_eq!(separate[..samples.len() / 2], combined[..samples.len() / 2]);
+
+    let (mut reader, mut decoder) = open();
+    let seeked = reader
+        .seek(
+            SeekMode::Accurate,
+            SeekTo::Time {
+                time: Time::try_new(1, 0).unwrap(),
+                track_id: None,
+            },
+        )
+        .unwrap();
+    // Seeks land on the last frame with a config, which is sent every 10 frames
+    assert_eq!(seeked.actual_ts.get(), 40 * 1024);
+    decoder.reset();
+    let frames = decode_aac_frames(&mut *reader, &mut *decoder, usize::MAX, |_, packet| packet);
+    let mut samples = frames.concat();
+    decoder.finalize();
+    decoder.last_decoded().copy_to_vec_interleaved(&mut drained);
+    samples.extend_from_slice(&drained);
+    // The first frame after a seek is faded in rather than dropped
+    assert_eq!(frames.len(), 79 - 40);
+    assert_eq!(samples.len(), frames.len() * 1024 * 2);
+}
+
+#[test]
+fn test_aac_loas_subframes() {
+    let file = File::open("../assets/aac-lc-subframes.loas").unwrap();
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut reader = LoasReader::try_new(mss, Default::default()).unwrap();
+    let track = reader.default_track(TrackType::Audio).unwrap();
+    let Some(CodecParameters::Audio(codec_params)) = track.codec_params.as_ref() else {
+        panic!("invalid params");
+    };
+    // Each frame carries two access units
+    assert_eq!(codec_params.max_frames_per_packet, Some(2048));
+    let mut decoder = AacDecoder::try_registry_new(codec_params, &Default::default()).unwrap();
+
+    let mut packets = 0;
+    let mut samples = Vec::new();
+    while let Some(packet) = reader.next_packet().unwrap() {
+        assert_eq!(packet.pts.get(), packets * 2048);
+        assert_eq!(packet.dur.get(), 2048);
+        let mut decoded = Vec::new();
+        decoder
+            .decode(&packet)
+            .unwrap()
+            .copy_to_vec_interleaved::<i16>(&mut decoded);
+        samples.extend_from_slice(&decoded);
+        packets += 1;
+    }
+    decoder.finalize();
+    let mut drained = Vec::new();
+    decoder.last_decoded().copy_to_vec_interleaved(&mut drained);
+    samples.extend_from_slice(&drained);
+    assert_eq!(packets, 44);
+    assert_eq!(samples.len(), packets as usize * 2048 * 2);
+    assert!(samples.iter().any(|s| s.unsigned_abs() > 1000));
+}
+
+#[test]
+fn test_audio_specific_config() {
+    let round_trip = |asc: &[u8]| {
+        let config = AudioSpecificConfig::read(asc).unwrap();
+        assert_eq!(config.to_bytes(), asc);
+        config
+    };
+
+    let lc = round_trip(&[0x12, 0x10]);
+    assert_eq!(lc.object_type, M4AType::Lc);
+    assert_eq!(lc.output_sample_rate(), 44100);
+    assert_eq!(lc.samples_per_frame(), Some(1024));
+    assert_eq!(lc.channels(), Some(layouts::CHANNEL_LAYOUT_STEREO));
+
+    // HE-AAC v2 signaled hierarchically
+    let he_v2 = round_trip(&[0xeb, 0x09, 0x88, 0x00]);
+    assert_eq!(he_v2.object_type, M4AType::Lc);
+    assert_eq!(
+        he_v2.sbr,
+        Some(SbrConfig {
+            signaling: SbrSignaling::Hierarchical,
+            present: true,
+            sample_rate: 48000,
+            ps: Some(true),
+        })
+    );
+    assert_eq!(he_v2.output_sample_rate(), 48000);
+    assert_eq!(he_v2.samples_per_frame(), Some(2048));
+
+    // SBR and PS signaled after a sync word
+    let backward_compatible = round_trip(&[0x13, 0x88, 0x56, 0xe5, 0xa5, 0x48, 0x80]);
+    assert_eq!(backward_compatible.sample_rate, 22050);
+    assert_eq!(
+        backward_compatible.sbr,
+        Some(SbrConfig {
+            signaling: SbrSignaling::BackwardCompatible,
+            present: true,
+            sample_rate: 44100,
+            ps: Some(true),
+        })
+    );
+    assert_eq!(backward_compatible.samples_per_frame(), Some(2048));
+
+    // 5.1 defined by a program config element
+    let pce = round_trip(&[0x11, 0x80, 0x04, 0xc8, 0x05, 0x00, 0x01, 0x08, 0x80, 0x00]);
+    assert_eq!(pce.channel_config, 0);
+    assert_eq!(pce.channels(), Some(layouts::CHANNEL_LAYOUT_AAC_5P1));
+
+    // AAC LD with 480 sample frames, error resilience tools and error protection
+    let ld = round_trip(&[0xb9, 0x8d, 0xa4]);
+    assert_eq!(ld.object_type, M4AType::ER_AAC_LD);
+    assert_eq!(ld.samples_per_frame(), Some(480));
+    assert_eq!(ld.ep_config, Some(1));
+
+    // The extension flags are kept even without error resilience tools
+    let extended = round_trip(&[0x12, 0x11, 0x00]);
+    let SpecificConfig::Ga(ga) = &extended.specific_config else {
+        panic!("invalid config");
+    };
+    assert!(ga.extension_flag);
+    assert!(!ga.extension_flag_3);
+    assert_eq!(ga.resilience, None);
+
+    // ER TwinVQ has no error resilience tools
+    let twin_vq = round_trip(&[0xaa, 0x11, 0x80]);
+    assert_eq!(twin_vq.object_type, M4AType::ER_TwinVQ);
+    let SpecificConfig::Ga(ga) = &twin_vq.specific_config else {
+        panic!("invalid config");
+    };
+    assert_eq!(ga.resilience, None);
+    assert!(ga.extension_flag_3);
+    assert_eq!(twin_vq.ep_config, Some(0));
+
+    // Invalid configs have no frame length
+    let mut invalid = lc.clone();
+    invalid.sample_rate = 0;
+    assert_eq!(invalid.samples_per_frame(), None);
+
+    // Sample rates outside the table are escaped
+    let escaped = round_trip(&[0x17, 0x80, 0x49, 0xd4, 0x10]);
+    assert_eq!(escaped.sample_rate, 37800);
+
+    let usac = round_trip(&[0xf9, 0x46, 0x23, 0x21, 0x00, 0x00]);
+    assert!(matches!(usac.specific_config, SpecificConfig::Usac(_)));
+    assert_eq!(usac.output_sample_rate(), 48000);
+    assert_eq!(usac.channels(), Some(layouts::CHANNEL_LAYOUT_MONO));
+    let mut invalid = usac.clone();
+    let SpecificConfig::Usac(config) = &mut invalid.specific_config else {
+        panic!("invalid config");
+    };
+    config.core_sbr_frame_length_index = 7;
+    assert_eq!(invalid.samples_per_frame(), None);
+
+    for (path, object_type, samples) in [
+        ("../assets/music.m4a", M4AType::Lc, 1024),
+        ("../assets/aac-ld.m4a", M4AType::ER_AAC_LD, 480),
+        ("../assets/aac-eld.m4a", M4AType::ER_AAC_ELD, 512),
+    ] {
+        let (_, decoder) = open_aac_file(path, |_| {});
+        let config = round_trip(decoder.codec_params().extra_data.as_ref().unwrap());
+        assert_eq!(config.object_type, object_type);
+        assert_eq!(config.samples_per_frame(), Some(samples));
+    }
+}
+
 #[test]
 fn test_decode_opus() {
     test_decode(File::open("../assets/sample.opus").unwrap());
 }
 
+#[test]
+fn test_opus_head_strict() {
+    let file = File::open("../assets/sample.opus").unwrap();
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let reader = get_probe()
+        .probe(&Hint::new(), mss, Default::default(), Default::default())
+        .unwrap();
+    let track = reader.default_track(TrackType::Audio).unwrap();
+    let Some(CodecParameters::Audio(codec_params)) = track.codec_params.as_ref() else {
+        panic!("invalid params");
+    };
+
+    let head = OpusHead::read(codec_params.extra_data.as_ref().unwrap()).unwrap();
+    assert_eq!(head.layout, OpusHeadLayout::Ogg);
+    assert_eq!(
+        head.channels as usize,
+        codec_params.channels.as_ref().unwrap().count()
+    );
+    assert!(head.is_version_supported());
+
+    // Minor version updates are compatible, major ones aren't
+    let mut extra_data = codec_params.extra_data.clone().unwrap();
+    extra_data[8] = 15;
+    assert!(OpusHead::read(&extra_data).unwrap().is_version_supported());
+    extra_data[8] = 16;
+    assert!(!OpusHead::read(&extra_data).unwrap().is_version_supported());
+
+    let opts = OpusDecoderOptions::default().strict(true);
+    OpusDecoder::try_new_with_options(codec_params, &Default::default(), &opts).unwrap();
+
+    let mut truncated = codec_params.clone();
+    truncated.extra_data = Some(codec_params.extra_data.as_ref().unwrap()[..12].into());
+    OpusDecoder::try_new_with_options(&truncated, &Default::default(), &opts).unwrap_err();
+    OpusDecoder::try_new_with_options(&truncated, &Default::default(), &Default::default())
+        .unwrap();
+}
+
+#[test]
+fn test_opus_head_dops() {
+    // 5.1 dOps box: version, channels, pre-skip, input sample rate, output gain, mapping family,
+    // stream count, coupled count, mapping table
+    let dops = [
+        0, 6, 0x01, 0x38, 0, 0, 0xbb, 0x80, 0xff, 0x00, 1, 4, 2, 0, 4, 1, 2, 3, 5,
+    ];
+    let head = OpusHead::read(&dops).unwrap();
+    assert_eq!(head.layout, OpusHeadLayout::Mp4);
+    assert_eq!(head.channels, 6);
+    assert_eq!(head.pre_skip, 312);
+    assert_eq!(head.input_sample_rate, 48000);
+    assert_eq!(head.output_gain, -256);
+    assert_eq!(head.channel_mapping.family, 1);
+    assert_eq!(head.channel_mapping.streams, 4);
+    assert_eq!(head.channel_mapping.coupled_streams, 2);
+    assert_eq!(head.channel_mapping.mapping, [0, 4, 1, 2, 3, 5]);
+
+    let mut prefixed = b"OpusHead".to_vec();
+    prefixed.extend_from_slice(&dops);
+    assert_eq!(OpusHead::read(&prefixed).unwrap(), head);
+
+    // Headers without a signature are only read as dOps if they fit its layout exactly
+    let padded = [&dops[..], &[0]].concat();
+    OpusHead::read(&dops[..dops.len() - 1]).unwrap_err();
+    OpusHead::read(&padded).unwrap_err();
+    let mut version = dops;
+    version[0] = 1;
+    OpusHead::read(&version).unwrap_err();
+    assert_eq!(
+        OpusHead::read_layout(&padded, OpusHeadLayout::Mp4).unwrap(),
+        head
+    );
+    OpusHead::read_layout(&dops, OpusHeadLayout::Ogg).unwrap_err();
+
+    let mut params = AudioCodecParameters::new();
+    params
+        .for_codec(CODEC_ID_OPUS)
+        .with_sample_rate(48000)
+        .with_channels(layouts::CHANNEL_LAYOUT_5P1)
+        .with_extra_data(dops.into());
+    let decoder = OpusDecoder::try_new_with_options(
+        &params,
+        &Default::default(),
+        &OpusDecoderOptions::default().strict(true),
+    )
+    .unwrap();
+    assert_eq!(
+        decoder.last_decoded().spec().channels(),
+        &layouts::CHANNEL_LAYOUT_5P1
+    );
+}
+
+#[test]
+fn test_opus_multistream() {
+    // Mapping family 1 tables as written by opusenc, with the channels in Vorbis order
+    for (mapping, coupled_streams, layout, vorbis_to_canonical) in [
+        (
+            vec![0, 4, 1, 2, 3, 5],
+            2,
+            layouts::CHANNEL_LAYOUT_5P1,
+            // L C R RL RR LFE -> L R C LFE RL RR
+            vec![0, 2, 1, 4, 5, 3],
+        ),
+        (
+            vec![0, 6, 1, 2, 3, 4, 5, 7],
+            3,
+            layouts::CHANNEL_LAYOUT_7P1,
+            // L C R SL SR RL RR LFE -> L R C LFE RL RR SL SR
+            vec![0, 2, 1, 6, 7, 4, 5, 3],
+        ),
+    ] {
+        let num_channels = mapping.len();
+        // Each input channel gets a different level so it can be told apart in the output
+        let amplitude = |vorbis_idx: usize| 0.05 * (vorbis_idx + 1) as f32;
+        let frames = 48000;
+        let tone = |vorbis_idx: usize| -> Vec<f32> {
+            (0..frames)
+                .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / 48000.0).sin())
+                .map(|s| s * amplitude(vorbis_idx))
+                .collect()
+        };
+
+        // Encode the decoder channels of each stream, then join the streams into multistream
+        // packets where all but the last one are self-delimited
+        let streams = num_channels - coupled_streams;
+        let stream_packets: Vec<Vec<Packet>> = (0..streams)
+            .map(|stream| {
+                let decoder_channels = if stream < coupled_streams {
+                    vec![stream * 2, stream * 2 + 1]
+                } else {
+                    vec![stream + coupled_streams]
+                };
+                let inputs: Vec<Vec<f32>> = decoder_channels
+                    .iter()
+                    .map(|channel| {
+                        tone(
+                            mapping
+                                .iter()
+                                .position(|mapped| *mapped as usize == *channel)
+                                .unwrap(),
+                        )
+                    })
+                    .collect();
+                let pcm: Vec<f32> = (0..frames)
+                    .flat_map(|i| inputs.iter().map(move |input| input[i]))
+                    .collect();
+                let layout = if inputs.len() == 2 {
+                    layouts::CHANNEL_LAYOUT_STEREO
+                } else {
+                    layouts::CHANNEL_LAYOUT_MONO
+                };
+                encode_opus(&AudioSpec::new(48000, layout), &pcm, &Default::default())
+            })
+            .collect();
+        let packets: Vec<Packet> = (0..stream_packets[0].len())
+            .map(|i| {
+                let mut data = Vec::new();
+                for (stream, packets) in stream_packets.iter().enumerate() {
+                    let packet = &packets[i].data;
+                    if stream == streams - 1 {
+                        data.extend_from_slice(packet);
+                        continue;
+                    }
+                    // Single frame packets only need the frame length after the TOC byte
+                    assert_eq!(packet[0] & 0x3, 0);
+                    let len = packet.len() - 1;
+                    data.push(packet[0]);
+                    if len < 252 {
+                        data.push(len as u8);
+                    } else {
+                        let first = 252 + (len & 0x3);
+                        data.extend([first as u8, ((len - first) >> 2) as u8]);
+                    }
+                    data.extend_from_slice(&packet[1..]);
+                }
+                let first = &stream_packets[0][i];
+                Packet::new(first.track_id, first.pts, first.dur, data)
+            })
+            .collect();
+
+        let head = OpusHead {
+            layout: OpusHeadLayout::Ogg,
+            version: 1,
+            channels: num_channels as u8,
+            pre_skip: 0,
+            input_sample_rate: 48000,
+            output_gain: 0,
+            channel_mapping: ChannelMapping {
+                family: 1,
+                streams: streams as u8,
+                coupled_streams: coupled_streams as u8,
+                mapping: mapping.clone(),
+            },
+        };
+        let mut params = AudioCodecParameters::new();
+        params
+            .for_codec(CODEC_ID_OPUS)
+            .with_sample_rate(48000)
+            .with_channels(layout.clone())
+            .with_extra_data(head.to_bytes(OpusHeadLayout::Ogg).into());
+        let mut decoder =
+            OpusDecoder::try_new_with_options(&params, &Default::default(), &Default::default())
+                .unwrap();
+        let mut planes = vec![Vec::new(); num_channels];
+        for packet in &packets {
+            let decoded = decoder.decode(packet).unwrap();
+            assert_eq!(decoded.spec().channels(), &layout);
+            let mut samples: Vec<f32> = Vec::new();
+            decoded.copy_to_vec_interleaved(&mut samples);
+            for frame in samples.chunks(num_channels) {
+                for (plane, sample) in planes.iter_mut().zip(frame) {
+                    plane.push(*sample);
+                }
+            }
+        }
+
+        for (vorbis_idx, canonical_idx) in vorbis_to_canonical.iter().enumerate() {
+            // Skip the start while the encoder and decoder settle
+            let plane = &planes[*canonical_idx][4800..];
+            let rms = (plane.iter().map(|s| s.powi(2)).sum::<f32>() / plane.len() as f32).sqrt();
+            let expected = amplitude(vorbis_idx) / 2f32.sqrt();
+            assert!(
+                (rms - expected).abs() < expected * 0.1,
+                "channel {vorbis_idx} of {num_channels} has rms {rms}, expected {expected}"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_opus_codec_params() {
+    let samples = decode_opus_with_params(
+        &Default::default(),
+        // The identification header takes precedence over the container
+        |params| params.channels = Some(layouts::CHANNEL_LAYOUT_MONO),
+        |i, packet, decoder| {
+            let params = decoder.codec_params();
+            assert_eq!(params.channels, Some(layouts::CHANNEL_LAYOUT_STEREO));
+            assert_eq!(params.sample_rate, Some(48000));
+            assert_eq!(params.frames_per_block.is_some(), i > 0);
+            Some(packet)
+        },
+    );
+    assert!(!samples.is_empty());
+}
+
+#[test]
+fn test_opus_output_gain() {
+    let reference = decode_opus(&OpusDecoderOptions::default().output_gain(OutputGain::Disabled));
+    // -6.02 dB in Q7.8 halves the amplitude
+    let attenuated =
+        decode_opus(&OpusDecoderOptions::default().output_gain(OutputGain::Custom(-1541)));
+    assert_eq!(reference.len(), attenuated.len());
+
+    let peak = |samples: &[f32]| samples.iter().fold(0f32, |acc, s| acc.max(s.abs()));
+    let ratio = peak(&attenuated) / peak(&reference);
+    assert!((ratio - 0.5).abs() < 0.01, "unexpected gain ratio {ratio}");
+}
+
+#[test]
+fn test_opus_packet_loss_concealment() {
+    let reference = decode_opus(&Default::default());
+    // Returns the output along with the range of samples that were concealed
+    let decode_with_loss = |opts: &OpusDecoderOptions, empty: bool| {
+        let mut pre_skip = 0;
+        let mut channels = 0;
+        let mut lost = 0..0;
+        let samples = decode_opus_with_params(
+            opts,
+            |params| {
+                let extra_data = params.extra_data.as_ref().unwrap();
+                pre_skip = u16::from_le_bytes([extra_data[10], extra_data[11]]) as usize;
+                channels = extra_data[9] as usize;
+            },
+            |i, mut packet, _| {
+                let end = if empty { 21 } else { 23 };
+                if i == 20 {
+                    lost.start = packet.pts.get() as usize;
+                }
+                if i == end {
+                    lost.end = packet.pts.get() as usize;
+                }
+                if empty && i == 20 {
+                    packet.data = Box::new([]);
+                }
+                (empty || !(20..23).contains(&i)).then_some(packet)
+            },
+        );
+        let lost = (lost.start - pre_skip) * channels..(lost.end - pre_skip) * channels;
+        (samples, lost)
+    };
+
+    // Drop a few packets so that the decoder sees a timestamp gap
+    let with_gap = decode_with_loss(&Default::default(), false);
+    // Empty packets are concealed with the duration of the previous packet
+    let with_empty = decode_with_loss(&Default::default(), true);
+    let with_fec = decode_with_loss(&OpusDecoderOptions::default().fec(true), false);
+    for (samples, lost) in [with_gap, with_empty, with_fec] {
+        assert_eq!(reference.len(), samples.len());
+        // Everything before the loss is untouched and the gap is filled with concealed audio
+        assert_eq!(samples[..lost.start], reference[..lost.start]);
+        let concealed = &samples[lost.clone()];
+        assert!(concealed.iter().any(|s| *s != 0.0));
+        assert_ne!(concealed, &reference[lost]);
+    }
+}
+
+#[test]
+fn test_opus_fec() {
+    // A voiced signal at a low bitrate so the encoder uses SILK, which carries the FEC data
+    let spec = AudioSpec::new(48000, layouts::CHANNEL_LAYOUT_MONO);
+    let mut phase = 0.0;
+    let pcm: Vec<f32> = (0..48000 * 2)
+        .map(|i| {
+            let t = i as f32 / 48000.0;
+            // Syllables with a varying pitch, since FEC data is only sent for active speech
+            phase += (150.0 + 40.0 * (t * 3.0 * std::f32::consts::TAU).sin()) / 48000.0;
+            let envelope = (t * 4.0 * std::f32::consts::TAU).sin().max(0.0);
+            (1..8)
+                .map(|h| (phase * h as f32 * std::f32::consts::TAU).sin() / h as f32)
+                .sum::<f32>()
+                * envelope
+                * 0.2
+        })
+        .collect();
+    let opts = OpusEncoderOptions::default()
+        .application(Application::Voip)
+        .signal(Signal::Voice)
+        .bitrate(Bitrate::Bits(24000))
+        .fec(true)
+        .expected_loss(20);
+    let packets = encode_opus(&spec, &pcm, &opts);
+    let lost = |i: usize| (20..90).step_by(10).any(|lost| lost == i);
+
+    let reference = decode_opus_packets(&spec, &packets, &Default::default(), |_| false);
+    let concealed = decode_opus_packets(&spec, &packets, &Default::default(), lost);
+    let recovered = decode_opus_packets(
+        &spec,
+        &packets,
+        &OpusDecoderOptions::default().fec(true),
+        lost,
+    );
+    assert_eq!(reference.len(), concealed.len());
+    assert_eq!(reference.len(), recovered.len());
+
+    let error = |samples: &[f32]| -> f32 {
+        samples
+            .iter()
+            .zip(&reference)
+            .map(|(a, b)| (a - b).powi(2))
+            .sum()
+    };
+    let (concealed, recovered) = (error(&concealed), error(&recovered));
+    assert!(
+        recovered < concealed / 10.0,
+        "fec {recovered} concealment {concealed}"
+    );
+}
+
+#[test]
+fn test_opus_seek_pre_roll() {
+    let reference = decode_opus(&Default::default());
+    let mut pre_skip = 0;
+    let mut channels = 0;
+    let mut seek_pts = 0;
+    let pre_roll = 3840;
+    let trim_start = 100;
+    let samples = decode_opus_with_params(
+        &Default::default(),
+        |params| {
+            let extra_data = params.extra_data.as_ref().unwrap();
+            pre_skip = u16::from_le_bytes([extra_data[10], extra_data[11]]) as usize;
+            channels = extra_data[9] as usize;
+        },
+        |i, mut packet, decoder| {
+            if i == 50 {
+                seek_pts = packet.pts.get();
+                AudioDecoder::reset(decoder);
+            }
+            // The demuxer's trimming is still applied once the pre-roll has been discarded, so
+            // seeks stay sample-accurate
+            if i > 50 && packet.pts.get() == seek_pts + pre_roll as i64 {
+                packet.trim_start = Duration::new(trim_start as u64);
+            }
+            Some(packet)
+        },
+    );
+    assert_eq!(
+        reference.len() - samples.len(),
+        (pre_roll + trim_start) * channels
+    );
+    let seek_offset = (seek_pts as usize - pre_skip) * channels;
+    assert_eq!(samples[..seek_offset], reference[..seek_offset]);
+    // The decoder has converged after the pre-roll, so the output lines up with the reference
+    let target_offset = seek_offset + (pre_roll + trim_start) * channels;
+    let decoded = &samples[seek_offset..];
+    let expected = &reference[target_offset..];
+    assert_eq!(decoded.len(), expected.len());
+    let error: f32 = decoded
+        .iter()
+        .zip(expected)
+        .map(|(a, b)| (a - b).powi(2))
+        .sum();
+    let energy: f32 = expected.iter().map(|s| s.powi(2)).sum();
+    assert!(error < energy * 0.001, "{error} {energy}");
+}
+
+#[test]
+fn test_opus_long_pre_skip() {
+    let reference = decode_opus(&Default::default());
+    let mut pre_skip = 0;
+    let mut channels = 0;
+    let samples = decode_opus_with_params(
+        &Default::default(),
+        |params| {
+            let mut extra_data = params.extra_data.clone().unwrap();
+            pre_skip = u16::from_le_bytes([extra_data[10], extra_data[11]]) as usize;
+            channels = extra_data[9] as usize;
+            // Pre-skip spanning several 20 ms packets
+            extra_data[10..12].copy_from_slice(&3840u16.to_le_bytes());
+            params.extra_data = Some(extra_data);
+        },
+        |_, packet, _| Some(packet),
+    );
+    assert_eq!(
+        reference.len() - samples.len(),
+        (3840 - pre_skip) * channels
+    );
+}
+
+#[test]
+fn test_opus_encode() {
+    let frames = 48123;
+    let spec = AudioSpec::new(48000, layouts::CHANNEL_LAYOUT_STEREO);
+    let pcm: Vec<f32> = (0..frames * 2)
+        .map(|i| (((i / 2) as f32) * 440.0 * std::f32::consts::TAU / 48000.0).sin() * 0.5)
+        .collect();
+
+    // Errors from libopus are reported as encode errors rather than decode errors
+    let opts = OpusEncoderOptions::default().bitrate(Bitrate::Bits(0));
+    let Err(Error::IoError(e)) = OpusEncoder::try_new(48000, layouts::CHANNEL_LAYOUT_STEREO, &opts)
+    else {
+        panic!("expected an encode error");
+    };
+    let e = e.get_ref().unwrap().downcast_ref::<EncodeError>().unwrap();
+    assert_eq!(e.context, "error configuring encoder");
+
+    let opts = OpusEncoderOptions::default().frame_duration(FrameDuration::Ms10);
+    let mut encoder = OpusEncoder::try_new(48000, layouts::CHANNEL_LAYOUT_STEREO, &opts).unwrap();
+    let pre_skip = encoder.pre_skip() as u64;
+    assert!(pre_skip > 0);
+    assert_eq!(encoder.head().pre_skip as u64, pre_skip);
+
+    let mut packets = Vec::new();
+    for chunk in pcm.chunks(2000) {
+        let mut buf = AudioBuffer::<f32>::new(spec.clone(), chunk.len() / 2);
+        buf.render_uninit(None);
+        buf.copy_from_slice_interleaved(&chunk);
+        packets.extend(encoder.encode(buf.as_generic_audio_buffer_ref()).unwrap());
+    }
+    packets.extend(encoder.flush().unwrap());
+
+    // Timestamps are contiguous and only the last packet is trimmed
+    let mut next_pts = 0;
+    for packet in &packets {
+        assert_eq!(packet.pts.get(), next_pts);
+        next_pts += (packet.dur.get() + packet.trim_end.get()) as i64;
+    }
+    let total: u64 = packets.iter().map(|packet| packet.dur.get()).sum();
+    assert_eq!(total, frames as u64 + pre_skip);
+
+    let mut params = AudioCodecParameters::new();
+    params
+        .for_codec(CODEC_ID_OPUS)
+        .with_sample_rate(48000)
+        .with_channels(layouts::CHANNEL_LAYOUT_STEREO);
+    let mut decoder =
+        OpusDecoder::try_new_with_options(&params, &Default::default(), &Default::default())
+            .unwrap();
+    let mut samples: Vec<f32> = Vec::new();
+    let mut decoded = Vec::new();
+    for packet in &packets {
+        decoder
+            .decode(packet)
+            .unwrap()
+            .copy_to_vec_interleaved(&mut decoded);
+        samples.extend_from_slice(&decoded);
+    }
+    assert_eq!(samples.len() as u64, (frames as u64 + pre_skip) * 2);
+}
+
+#[test]
+fn test_ogg_opus_remux() {
+    let file = File::open("../assets/sample.opus").unwrap();
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut reader = get_probe()
+        .probe(&Hint::new(), mss, Default::default(), Default::default())
+        .unwrap();
+    let track = reader.default_track(TrackType::Audio).unwrap();
+    let track_id = track.id;
+    let Some(CodecParameters::Audio(codec_params)) = track.codec_params.as_ref() else {
+        panic!("invalid params");
+    };
+    let head = OpusHead::read(codec_params.extra_data.as_ref().unwrap()).unwrap();
+
+    let mut writer = OggOpusWriter::try_new(Vec::new(), 1, &head, &OpusTags::default()).unwrap();
+    while let Some(packet) = reader.next_packet().unwrap() {
+        if packet.track_id == track_id {
+            writer.write_packet(&packet).unwrap();
+        }
+    }
+    let remuxed = writer.finish().unwrap();
+
+    let reference = decode_opus(&Default::default());
+    let samples = decode_opus_source(
+        Box::new(Cursor::new(remuxed)),
+        &Default::default(),
+        |_| {},
+        |_, packet, _| Some(packet),
+    );
+    assert_eq!(reference, samples);
+}
+
+#[test]
+fn test_ogg_opus_encode() {
+    let frames = 30011;
+    let spec = AudioSpec::new(24000, layouts::CHANNEL_LAYOUT_MONO);
+    let pcm: Vec<f32> = (0..frames)
+        .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / 24000.0).sin() * 0.5)
+        .collect();
+    let mut buf = AudioBuffer::<f32>::new(spec, frames);
+    buf.render_uninit(None);
+    buf.copy_from_slice_interleaved(&pcm);
+
+    let mut encoder =
+        OpusEncoder::try_new(24000, layouts::CHANNEL_LAYOUT_MONO, &Default::default()).unwrap();
+    let mut writer =
+        OggOpusWriter::try_new(Vec::new(), 1, &encoder.head(), &OpusTags::default()).unwrap();
+    let packets = encoder.encode(buf.as_generic_audio_buffer_ref()).unwrap();
+    for packet in packets.iter().chain(&encoder.flush().unwrap()) {
+        writer.write_packet(packet).unwrap();
+    }
+    let encoded = writer.finish().unwrap();
+
+    // Pre-skip and end trimming leave exactly the input, resampled to 48 kHz by the decoder
+    let samples = decode_opus_source(
+        Box::new(Cursor::new(encoded)),
+        &Default::default(),
+        |_| {},
+        |_, packet, _| Some(packet),
+    );
+    assert_eq!(samples.len(), frames * 2);
+}
+
+#[test]
+fn test_opus_packet() {
+    // Code 0: one 20 ms fullband CELT frame
+    let packet = OpusPacket::parse(&[0xfc, 1, 2, 3]).unwrap();
+    assert_eq!(packet.mode(), OpusMode::Celt);
+    assert_eq!(packet.bandwidth(), Bandwidth::Fullband);
+    assert!(packet.is_stereo());
+    assert_eq!(packet.frames(), [&[1, 2, 3][..]]);
+    assert_eq!(packet.samples(48000), 960);
+
+    // Code 2: two narrowband SILK frames with explicit lengths
+    let packet = OpusPacket::parse(&[0x0a, 2, 1, 1, 2, 2, 2]).unwrap();
+    assert_eq!(packet.mode(), OpusMode::Silk);
+    assert_eq!(packet.frames(), [&[1, 1][..], &[2, 2, 2]]);
+    assert_eq!(packet.samples(16000), 640);
+
+    // Code 3: three VBR frames with two bytes of padding
+    let packet = OpusPacket::parse(&[0x83, 0xc3, 2, 1, 2, 1, 2, 2, 3, 3, 3, 0, 0]).unwrap();
+    assert!(packet.is_vbr());
+    assert_eq!(packet.frames(), [&[1][..], &[2, 2], &[3, 3, 3]]);
+    assert_eq!(packet.padding(), 2);
+    assert_eq!(packet.samples(48000), 360);
+
+    // Self-delimited framing stores the length of the last frame
+    let (packet, len) = OpusPacket::parse_self_delimited(&[0xfc, 2, 9, 9, 0xfc, 7]).unwrap();
+    assert_eq!(packet.frames(), [&[9, 9][..]]);
+    assert_eq!(len, 4);
+
+    OpusPacket::parse(&[]).unwrap_err();
+    // Odd length for equal-sized frames
+    OpusPacket::parse(&[0x09, 1, 2, 3]).unwrap_err();
+    // 180 ms of SILK frames
+    OpusPacket::parse(&[0x1b, 0x03, 1, 2, 3]).unwrap_err();
+    // Padding longer than the packet
+    OpusPacket::parse(&[0x83, 0x41, 10, 1]).unwrap_err();
+
+    let file = File::open("../assets/sample.opus").unwrap();
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut reader = get_probe()
+        .probe(&Hint::new(), mss, Default::default(), Default::default())
+        .unwrap();
+    while let Some(packet) = reader.next_packet().unwrap() {
+        let parsed = OpusPacket::parse(&packet.data).unwrap();
+        assert_eq!(
+            parsed.samples(48000) as u64,
+            packet.dur.get() + packet.trim_start.get() + packet.trim_end.get()
+        );
+    }
+}
+
+fn encode_opus(spec: &AudioSpec, pcm: &[f32], opts: &OpusEncoderOptions) -> Vec<Packet> {
+    let num_channels = spec.channels().count();
+    let mut encoder = OpusEncoder::try_new(spec.rate(), spec.channels().clone(), opts).unwrap();
+    let mut buf = AudioBuffer::<f32>::new(spec.clone(), pcm.len() / num_channels);
+    buf.render_uninit(None);
+    buf.copy_from_slice_interleaved(&pcm);
+    let mut packets = encoder.encode(buf.as_generic_audio_buffer_ref()).unwrap();
+    packets.extend(encoder.flush().unwrap());
+    packets
+}
+
+/// Decodes encoded packets, leaving out the ones that are `lost`.
+fn decode_opus_packets<F>(
+    spec: &AudioSpec,
+    packets: &[Packet],
+    opts: &OpusDecoderOptions,
+    lost: F,
+) -> Vec<f32>
+where
+    F: Fn(usize) -> bool,
+{
+    let mut params = AudioCodecParameters::new();
+    params
+        .for_codec(CODEC_ID_OPUS)
+        .with_sample_rate(spec.rate())
+        .with_channels(spec.channels().clone());
+    let mut decoder =
+        OpusDecoder::try_new_with_options(&params, &Default::default(), opts).unwrap();
+    let mut samples = Vec::new();
+    let mut decoded = Vec::new();
+    for (i, packet) in packets.iter().enumerate() {
+        if lost(i) {
+            continue;
+        }
+        decoder
+            .decode(packet)
+            .unwrap()
+            .copy_to_vec_interleaved(&mut decoded);
+        samples.extend_from_slice(&decoded);
+    }
+    samples
+}
+
+fn decode_opus(opts: &OpusDecoderOptions) -> Vec<f32> {
+    decode_opus_with(opts, |_, packet| Some(packet))
+}
+
+fn decode_opus_with<F>(opts: &OpusDecoderOptions, mut map_packet: F) -> Vec<f32>
+where
+    F: FnMut(usize, Packet) -> Option<Packet>,
+{
+    decode_opus_with_decoder(opts, |i, packet, _| map_packet(i, packet))
+}
+
+fn decode_opus_with_decoder<F>(opts: &OpusDecoderOptions, map_packet: F) -> Vec<f32>
+where
+    F: FnMut(usize, Packet, &mut OpusDecoder) -> Option<Packet>,
+{
+    decode_opus_with_params(opts, |_| {}, map_packet)
+}
+
+fn decode_opus_with_params<P, F>(
+    opts: &OpusDecoderOptions,
+    map_params: P,
+    map_packet: F,
+) -> Vec<f32>
+where
+    P: FnOnce(&mut AudioCodecParameters),
+    F: FnMut(usize, Packet, &mut OpusDecoder) -> Option<Packet>,
+{
+    let file = File::open("../assets/sample.opus").unwrap();
+    decode_opus_source(Box::new(file), opts, map_params, map_packet)
+}
+
+fn decode_opus_source<P, F>(
+    source: Box<dyn MediaSource>,
+    opts: &OpusDecoderOptions,
+    map_params: P,
+    mut map_packet: F,
+) -> Vec<f32>
+where
+    P: FnOnce(&mut AudioCodecParameters),
+    F: FnMut(usize, Packet, &mut OpusDecoder) -> Option<Packet>,
+{
+    let mss = MediaSourceStream::new(source, Default::default());
+    let mut reader = get_probe()
+        .probe(&Hint::new(), mss, Default::default(), Default::default())
+        .unwrap();
+    let track = reader.default_track(TrackType::Audio).unwrap();
+    let track_id = track.id;
+    let Some(CodecParameters::Audio(codec_params)) = track.codec_params.as_ref() else {
+        panic!("invalid params");
+    };
+    let mut codec_params = codec_params.clone();
+    map_params(&mut codec_params);
+    let mut decoder =
+        OpusDecoder::try_new_with_options(&codec_params, &Default::default(), opts).unwrap();
+
+    let mut samples = Vec::new();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while let Some(packet) = reader.next_packet().unwrap() {
+        if packet.track_id != track_id {
+            continue;
+        }
+        i += 1;
+        let Some(packet) = map_packet(i - 1, packet, &mut decoder) else {
+            continue;
+        };
+        decoder
+            .decode(&packet)
+            .unwrap()
+            .copy_to_vec_interleaved(&mut decoded);
+        samples.extend_from_slice(&decoded);
+    }
+    samples
+}
+
+fn decode_aac<P>(map_params: P) -> Vec<i16>
+where
+    P: FnOnce(&mut AudioCodecParameters),
+{
+    let (mut reader, mut decoder) = open_aac(map_params);
+    decode_aac_frames(&mut *reader, &mut *decoder, usize::MAX, |_, packet| packet).concat()
+}
+
+fn decode_aac_with_options(aac_opts: &AacDecoderOptions) -> Vec<i16> {
+    let (mut reader, decoder) = open_aac(|_| {});
+    let mut decoder: Box<dyn AudioDecoder> = Box::new(
+        AacDecoder::try_new_with_options(decoder.codec_params(), &Default::default(), aac_opts)
+            .unwrap(),
+    );
+    let mut samples =
+        decode_aac_frames(&mut *reader, &mut *decoder, usize::MAX, |_, packet| packet).concat();
+    let mut drained = Vec::new();
+    decoder.finalize();
+    decoder.last_decoded().copy_to_vec_interleaved(&mut drained);
+    samples.extend_from_slice(&drained);
+    samples
+}
+
+fn open_aac<P>(map_params: P) -> (Box<dyn FormatReader>, Box<dyn AudioDecoder>)
+where
+    P: FnOnce(&mut AudioCodecParameters),
+{
+    open_aac_file("../assets/music.m4a", map_params)
+}
+
+fn open_aac_file<P>(path: &str, map_params: P) -> (Box<dyn FormatReader>, Box<dyn AudioDecoder>)
+where
+    P: FnOnce(&mut AudioCodecParameters),
+{
+    let file = File::open(path).unwrap();
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let reader = get_probe()
+        .probe(&Hint::new(), mss, Default::default(), Default::default())
+        .unwrap();
+    let track = reader.default_track(TrackType::Audio).unwrap();
+    let Some(CodecParameters::Audio(codec_params)) = track.codec_params.as_ref() else {
+        panic!("invalid params");
+    };
+    let mut codec_params = codec_params.clone();
+    map_params(&mut codec_params);
+    let decoder = AacDecoder::try_registry_new(&codec_params, &Default::default()).unwrap();
+    (reader, decoder)
+}
+
+/// Decodes up to `count` packets and returns the interleaved samples of each one.
+fn decode_aac_frames<F>(
+    reader: &mut dyn FormatReader,
+    decoder: &mut dyn AudioDecoder,
+    count: usize,
+    mut map_packet: F,
+) -> Vec<Vec<i16>>
+where
+    F: FnMut(usize, Packet) -> Packet,
+{
+    let track_id = reader.default_track(TrackType::Audio).unwrap().id;
+    let mut frames = Vec::new();
+    while frames.len() < count
+        && let Some(packet) = reader.next_packet().unwrap()
+    {
+        if packet.track_id != track_id {
+            continue;
+        }
+        let packet = map_packet(frames.len(), packet);
+        let mut decoded = Vec::new();
+        decoder
+            .decode(&packet)
+            .unwrap()
+            .copy_to_vec_interleaved(&mut decoded);
+        frames.push(decoded);
+    }
+    frames
+}
+
+/// Prepends an ADTS header built from the AudioSpecificConfig to a raw packet.
+fn adts_frame(asc: &[u8], payload: &[u8]) -> Vec<u8> {
+    let profile = (asc[0] >> 3) - 1;
+    let sample_rate_index = (asc[0] & 0x07) << 1 | asc[1] >> 7;
+    let channels = (asc[1] >> 3) & 0x0f;
+    let len = payload.len() + 7;
+    let mut frame = vec![
+        0xff,
+        0xf1,
+        profile << 6 | sample_rate_index << 2 | channels >> 2,
+        (channels & 0x03) << 6 | (len >> 11) as u8,
+        (len >> 3) as u8,
+        ((len & 0x07) << 5) as u8 | 0x1f,
+        0xfc,
+    ];
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Splits an ADTS stream into packets that keep their headers.
+fn adts_packets(path: &str, frame_len: u64) -> Vec<Packet> {
+    let data = std::fs::read(path).unwrap();
+    let mut packets = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = &data[pos..pos + 7];
+        let len = usize::from(header[3] & 0x03) << 11
+            | usize::from(header[4]) << 3
+            | usize::from(header[5] >> 5);
+        let ts = Timestamp::new((packets.len() as u64 * frame_len) as i64);
+        packets.push(Packet::new(
+            0,
+            ts,
+            Duration::new(frame_len),
+            data[pos..pos + len].to_vec(),
+        ));
+        pos += len;
+    }
+    packets
+}
+
+/// Wraps the body in an MPEG-4 descriptor, using the padded 4 byte size like most muxers.
+fn descriptor(tag: u8, body: &[u8]) -> Vec<u8> {
+    let len = body.len();
+    let mut descriptor = vec![
+        tag,
+        0x80 | (len >> 21) as u8 & 0x7f,
+        0x80 | (len >> 14) as u8 & 0x7f,
+        0x80 | (len >> 7) as u8 & 0x7f,
+        len as u8 & 0x7f,
+    ];
+    descriptor.extend_from_slice(body);
+    descriptor
+}
+
 fn test_decode(file: File) {
     let mss = MediaSourceStream::new(Box::new(file), Default::default());
     let mut reader = get_probe()